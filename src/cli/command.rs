@@ -9,10 +9,12 @@ Usage: nssi [COMMAND]
 Main Commands:
     voyage        Create your current dirctory into nss repository
     snap          Snapshot latest working directory
+    status        Show the working directory and snapshot status
     reg           Register snapshot(tree object) as commit object in local repository
-    bookmark      Cretae or replace bookmarker for specific commit
+    bookmark      Create, list, replace, rename or delete bookmarkers
     story         View commit history from a cuurent commit
     go-to         Go to the commit and change the working directory and index
+    merge         Join the history of the bookmark into the current one
 
 Deep Commnads:
     hasher        Calclate object hash or Create object
@@ -22,6 +24,7 @@ Deep Commnads:
     up-snap       Register file contents in the working diretory to the index
     write-tree    Create main tree object by index
     update-ref    Move HEAD pointer to the commit
+    reflog        Show the previous values of HEAD or the bookmark
 
 Develop Commands:
     debug         Try debug
@@ -46,10 +49,12 @@ Options:
             //main command
             voyage_command(),
             snap_command(),
+            status_command(),
             reg_command(),
             bookemark_command(),
             history_command(),
             goto_command(),
+            merge_command(),
             diff_command(),
         ])
         .subcommands(vec![
@@ -60,6 +65,7 @@ Options:
             index_command(),
             write_command(),
             ref_command(),
+            reflog_command(),
         ])
         .subcommands(vec![
             // development command
@@ -163,6 +169,10 @@ fn snap_command() -> clap::Command {
         )
 }
 
+fn status_command() -> clap::Command {
+    Command::new("status").about("Show the working directory and snapshot status")
+}
+
 fn reg_command() -> clap::Command {
     Command::new("reg")
         .about("Register snapshot(tree object) as commit object in local repository")
@@ -177,7 +187,7 @@ fn reg_command() -> clap::Command {
 
 fn bookemark_command() -> clap::Command {
     Command::new("bookmark")
-        .about("Cretae or replace bookmarker for specific commit")
+        .about("Create, list, replace, rename or delete bookmarkers")
         .arg(
             Arg::new("replace")
                 .short('r')
@@ -185,17 +195,51 @@ fn bookemark_command() -> clap::Command {
                 .action(ArgAction::SetTrue)
                 .help("Replace already existing bookmarks to another commit"),
         )
+        .arg(
+            Arg::new("list")
+                .short('l')
+                .long("list")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["replace", "delete", "move", "bookmarker"])
+                .help("List all bookmarks with the current one marked"),
+        )
+        .arg(
+            Arg::new("delete")
+                .short('d')
+                .long("delete")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["replace", "move", "hash"])
+                .help("Delete the bookmark merged into HEAD"),
+        )
+        .arg(
+            Arg::new("force")
+                .short('f')
+                .long("force")
+                .action(ArgAction::SetTrue)
+                .requires("delete")
+                .help("Delete the bookmark even if it is not merged"),
+        )
+        .arg(
+            Arg::new("move")
+                .short('m')
+                .long("move")
+                .visible_alias("rename")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("replace")
+                .requires("hash")
+                .help("Rename the bookmark (the second argument is the new name)"),
+        )
         .arg(
             Arg::new("bookmarker")
                 .value_parser(clap::builder::NonEmptyStringValueParser::new())
                 .help("... name (bookmarker name) to identify the commit")
                 .value_name("booknaker")
-                .required(true),
+                .required_unless_present("list"),
         )
         .arg(
             Arg::new("hash")
                 .value_parser(clap::builder::NonEmptyStringValueParser::new())
-                .help("... commit to leaving a bookmark")
+                .help("... commit to leaving a bookmark, or new name with --move")
                 .value_name("commit hash")
                 .required(false),
         )
@@ -204,6 +248,14 @@ fn bookemark_command() -> clap::Command {
 fn ref_command() -> clap::Command {
     Command::new("update-ref")
         .about("Move HEAD pointer to the commit")
+        .arg(
+            Arg::new("bookmark")
+                .short('b')
+                .long("bookmark")
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .help("Move the bookmark instead of HEAD")
+                .value_name("bookmarker"),
+        )
         .arg(
             Arg::new("hash")
                 .value_parser(clap::builder::NonEmptyStringValueParser::new())
@@ -211,6 +263,25 @@ fn ref_command() -> clap::Command {
                 .required(true)
                 .value_name("hash value"),
         )
+        .arg(
+            Arg::new("old-hash")
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .help("... commit expected before the update (40 zeros: no commit yet)")
+                .required(false)
+                .value_name("old hash value"),
+        )
+}
+
+fn reflog_command() -> clap::Command {
+    Command::new("reflog")
+        .about("Show the previous values of HEAD or the bookmark")
+        .arg(
+            Arg::new("reference")
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .help("... HEAD (default) or bookmarker")
+                .required(false)
+                .value_name("reference"),
+        )
 }
 
 fn history_command() -> clap::Command {
@@ -223,36 +294,166 @@ fn history_command() -> clap::Command {
                 .action(ArgAction::SetTrue)
                 .help("Short history"),
         )
+        .arg(
+            Arg::new("topo-order")
+                .long("topo-order")
+                .action(ArgAction::SetTrue)
+                .help("Show no parents before all of its children"),
+        )
+        .arg(
+            Arg::new("graph")
+                .long("graph")
+                .action(ArgAction::SetTrue)
+                .help("Draw the commit graph on the left side (implies --topo-order)"),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_name("format")
+                .conflicts_with("short")
+                .help("Preset (oneline, medium, full, raw) or placeholders like \"%h %an %s\""),
+        )
+        .arg(
+            Arg::new("max-count")
+                .short('n')
+                .long("max-count")
+                .value_parser(clap::value_parser!(usize))
+                .value_name("count")
+                .help("Limit the number of commits to output"),
+        )
+        .arg(
+            Arg::new("since")
+                .long("since")
+                .value_name("date")
+                .help("Show commits more recent than the date"),
+        )
+        .arg(
+            Arg::new("until")
+                .long("until")
+                .value_name("date")
+                .help("Show commits older than the date"),
+        )
+        .arg(
+            Arg::new("author")
+                .long("author")
+                .value_name("pattern")
+                .help("Show commits whose author matches the regex"),
+        )
+        .arg(
+            Arg::new("grep")
+                .long("grep")
+                .value_name("pattern")
+                .help("Show commits whose message matches the regex"),
+        )
+        .arg(Arg::new("revision").value_name("revision range").help(
+            "Start from the revision, or <from>..<to> for the commits reachable only from <to>",
+        ))
+        .arg(
+            Arg::new("paths")
+                .value_parser(clap::value_parser!(std::path::PathBuf))
+                .num_args(1..)
+                .last(true)
+                .value_name("path")
+                .help("Show commits changed the files under the paths"),
+        )
 }
 
 fn goto_command() -> clap::Command {
     Command::new("go-to")
         .about("Go to the commit and change the working directory and index")
+        .arg(
+            Arg::new("create")
+                .short('c')
+                .long("create")
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .help("Create a new bookmark at the commit (HEAD by default) and go to it")
+                .value_name("new bookmarker"),
+        )
+        .arg(
+            Arg::new("force")
+                .short('f')
+                .long("force")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("merge")
+                .help("Go even if local changes are overwritten"),
+        )
+        .arg(
+            Arg::new("merge")
+                .short('m')
+                .long("merge")
+                .action(ArgAction::SetTrue)
+                .help("Merge local changes into the files of the commit"),
+        )
         .arg(
             Arg::new("hash")
                 .value_parser(clap::builder::NonEmptyStringValueParser::new())
                 .help("...This object must be stored in the repository as commit object")
-                .required(true)
+                .required_unless_present("create")
                 .value_name("hash value"),
         )
 }
 
+fn merge_command() -> clap::Command {
+    Command::new("merge")
+        .about("Join the history of the bookmark into the current one")
+        .arg(
+            Arg::new("bookmarker")
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .help("... bookmarker (or commit) to be merged")
+                .required(true)
+                .value_name("bookmarker"),
+        )
+}
+
 fn diff_command() -> clap::Command {
     Command::new("diff")
-        .about("Display difference between target commit and another commit.")
-        .override_usage("\n\tnss diff <target commit> <another commit>")
+        .about("Display difference between commits, the index and the working directory.")
+        .override_usage(
+            "\n\tnss diff [<commit>]\n\tnss diff --staged [<commit>]\n\tnss diff <target commit> <another commit>",
+        )
+        .arg(
+            Arg::new("staged")
+                .long("staged")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("another-hash")
+                .help("Compare the index with HEAD (or the commit)"),
+        )
+        .arg(
+            Arg::new("name-status")
+                .long("name-status")
+                .action(ArgAction::SetTrue)
+                .help("Show only names and status of changed files"),
+        )
+        .arg(
+            Arg::new("unified")
+                .short('U')
+                .long("unified")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("3")
+                .help("Generate diffs with <n> lines of context")
+                .value_name("n"),
+        )
+        .arg(
+            Arg::new("diff-algorithm")
+                .long("diff-algorithm")
+                .value_parser(["myers", "patience", "histogram"])
+                .default_value("myers")
+                .help("Choose a diff algorithm")
+                .value_name("algorithm"),
+        )
         .arg(
             Arg::new("target-hash")
                 .value_parser(clap::builder::NonEmptyStringValueParser::new())
                 .help("... This object must be stored in the repository")
-                .required(true)
+                .required(false)
                 .value_name("target hash"),
         )
         .arg(
             Arg::new("another-hash")
                 .value_parser(clap::builder::NonEmptyStringValueParser::new())
                 .help("... This object must be stored in the repository")
-                .required(true)
+                .required(false)
+                .requires("target-hash")
                 .value_name("another hash"),
         )
 }
@@ -475,6 +676,19 @@ mod tests {
         assert!(res.as_mut().unwrap().get_flag("all"));
     }
 
+    #[test]
+    fn test_status_command() {
+        let mut cmd = status_command();
+
+        let res = cmd.try_get_matches_from_mut(vec!["status"]);
+        assert!(res.is_ok());
+
+        // Not exepected value
+        let res = cmd.try_get_matches_from_mut(vec!["status", "first.txt"]);
+        assert!(res.is_err());
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::UnknownArgument);
+    }
+
     #[test]
     fn test_reg_command() {
         let mut cmd = reg_command();
@@ -541,6 +755,33 @@ mod tests {
 
         assert!(res.is_ok());
         assert!(res.as_mut().unwrap().get_flag("replace"));
+
+        // Run with -l option
+        let mut res = cmd.try_get_matches_from_mut(vec!["bookmark", "-l"]);
+        assert!(res.is_ok());
+        assert!(res.as_mut().unwrap().get_flag("list"));
+        let res = cmd.try_get_matches_from_mut(vec!["bookmark", "-l", "develop"]);
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+
+        // Run with -d option
+        let mut res = cmd.try_get_matches_from_mut(vec!["bookmark", "-d", "-f", "develop"]);
+        assert!(res.is_ok());
+        assert!(res.as_mut().unwrap().get_flag("delete"));
+        assert!(res.as_mut().unwrap().get_flag("force"));
+        let res = cmd.try_get_matches_from_mut(vec!["bookmark", "-f", "develop"]);
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::MissingRequiredArgument);
+
+        // Run with --rename option
+        let mut res =
+            cmd.try_get_matches_from_mut(vec!["bookmark", "--rename", "develop", "feature/login"]);
+        assert!(res.is_ok());
+        assert!(res.as_mut().unwrap().get_flag("move"));
+        assert_eq!(
+            res.as_mut().unwrap().get_one::<String>("hash").unwrap(),
+            "feature/login"
+        );
+        let res = cmd.try_get_matches_from_mut(vec!["bookmark", "-m", "develop"]);
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::MissingRequiredArgument);
     }
 
     #[test]
@@ -564,6 +805,57 @@ mod tests {
             "6fawfwK234412"
         );
         assert!(res.as_mut().unwrap().try_get_one::<String>("test").is_err());
+
+        // Expected old commit of the bookmark
+        let mut res = cmd.try_get_matches_from_mut(vec![
+            "update-ref",
+            "-b",
+            "voyage",
+            "6fawfwK234412",
+            "c192349d0ee5",
+        ]);
+        assert!(res.is_ok());
+        assert_eq!(
+            res.as_mut().unwrap().get_one::<String>("bookmark").unwrap(),
+            "voyage"
+        );
+        assert_eq!(
+            res.as_mut().unwrap().get_one::<String>("old-hash").unwrap(),
+            "c192349d0ee5"
+        );
+
+        // Too many commits
+        let res = cmd.try_get_matches_from_mut(vec!["update-ref", "a", "b", "c"]);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_reflog_command() {
+        let mut cmd = reflog_command();
+
+        // No option
+        let mut res = cmd.try_get_matches_from_mut(vec!["reflog"]);
+        assert!(res.is_ok());
+        assert!(res
+            .as_mut()
+            .unwrap()
+            .get_one::<String>("reference")
+            .is_none());
+
+        // Get reference value
+        let mut res = cmd.try_get_matches_from_mut(vec!["reflog", "voyage"]);
+        assert!(res.is_ok());
+        assert_eq!(
+            res.as_mut()
+                .unwrap()
+                .get_one::<String>("reference")
+                .unwrap(),
+            "voyage"
+        );
+
+        // Not exepected value
+        let res = cmd.try_get_matches_from_mut(vec!["reflog", "voyage", "develop"]);
+        assert!(res.is_err());
     }
 
     #[test]
@@ -579,6 +871,177 @@ mod tests {
 
         assert!(res.is_ok());
         assert!(res.as_mut().unwrap().get_flag("short"));
+        assert!(!res.as_mut().unwrap().get_flag("topo-order"));
+
+        // Run with --topo-order option
+        let mut res = cmd.try_get_matches_from_mut(vec!["story", "--topo-order"]);
+
+        assert!(res.is_ok());
+        assert!(res.as_mut().unwrap().get_flag("topo-order"));
+
+        // Run with --graph option
+        let mut res = cmd.try_get_matches_from_mut(vec!["story", "-s", "--graph"]);
+
+        assert!(res.is_ok());
+        assert!(res.as_mut().unwrap().get_flag("graph"));
+        assert!(res.as_mut().unwrap().get_flag("short"));
+
+        // Run with filters
+        let mut res = cmd.try_get_matches_from_mut(vec![
+            "story",
+            "-n",
+            "3",
+            "--since",
+            "2023-06-01",
+            "--author",
+            "noshishi",
+            "--grep",
+            "^fix",
+            "voyage..develop",
+            "--",
+            "src",
+            "README.md",
+        ]);
+
+        assert!(res.is_ok());
+        let res = res.as_mut().unwrap();
+        assert_eq!(res.get_one::<usize>("max-count"), Some(&3));
+        assert_eq!(
+            res.get_one::<String>("revision").map(|s| s.as_str()),
+            Some("voyage..develop")
+        );
+        assert_eq!(
+            res.get_many::<std::path::PathBuf>("paths").unwrap().len(),
+            2
+        );
+
+        // Run with --format option
+        let mut res = cmd.try_get_matches_from_mut(vec!["story", "--format", "%h %s"]);
+
+        assert!(res.is_ok());
+        assert_eq!(
+            res.as_mut()
+                .unwrap()
+                .get_one::<String>("format")
+                .map(|s| s.as_str()),
+            Some("%h %s")
+        );
+
+        let res = cmd.try_get_matches_from_mut(vec!["story", "-s", "--format", "oneline"]);
+        assert!(res.is_err());
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+
+        // Count must be number
+        let res = cmd.try_get_matches_from_mut(vec!["story", "-n", "three"]);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_merge_command() {
+        let mut cmd = merge_command();
+
+        let res = cmd.try_get_matches_from_mut(vec!["merge"]);
+        assert!(res.is_err());
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::MissingRequiredArgument);
+
+        let mut res = cmd.try_get_matches_from_mut(vec!["merge", "develop"]);
+        assert!(res.is_ok());
+        assert_eq!(
+            res.as_mut()
+                .unwrap()
+                .get_one::<String>("bookmarker")
+                .unwrap(),
+            "develop"
+        );
+    }
+
+    #[test]
+    fn test_diff_command() {
+        let mut cmd = diff_command();
+
+        // Working directory and index
+        let mut res = cmd.try_get_matches_from_mut(vec!["diff"]);
+        assert!(res.is_ok());
+        assert!(!res.as_mut().unwrap().get_flag("staged"));
+        assert!(res
+            .as_mut()
+            .unwrap()
+            .get_one::<String>("target-hash")
+            .is_none());
+
+        // Index and HEAD
+        let mut res = cmd.try_get_matches_from_mut(vec!["diff", "--staged"]);
+        assert!(res.is_ok());
+        assert!(res.as_mut().unwrap().get_flag("staged"));
+
+        // Only one commit with staged option
+        let res =
+            cmd.try_get_matches_from_mut(vec!["diff", "--staged", "jfaf7GATG7ya", "6fawfwK234412"]);
+        assert!(res.is_err());
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+
+        // Commit and working directory
+        let mut res = cmd.try_get_matches_from_mut(vec!["diff", "jfaf7GATG7ya"]);
+        assert!(res.is_ok());
+        assert_eq!(
+            res.as_mut()
+                .unwrap()
+                .get_one::<String>("target-hash")
+                .unwrap(),
+            "jfaf7GATG7ya"
+        );
+
+        // Default option
+        let mut res = cmd.try_get_matches_from_mut(vec!["diff", "jfaf7GATG7ya", "6fawfwK234412"]);
+        assert!(res.is_ok());
+        assert_eq!(
+            res.as_mut().unwrap().get_one::<usize>("unified").unwrap(),
+            &3
+        );
+        assert_eq!(
+            res.as_mut()
+                .unwrap()
+                .get_one::<String>("diff-algorithm")
+                .unwrap(),
+            "myers"
+        );
+        assert!(!res.as_mut().unwrap().get_flag("name-status"));
+
+        // Run with options
+        let mut res = cmd.try_get_matches_from_mut(vec![
+            "diff",
+            "-U",
+            "5",
+            "--diff-algorithm",
+            "patience",
+            "--name-status",
+            "jfaf7GATG7ya",
+            "6fawfwK234412",
+        ]);
+        assert!(res.is_ok());
+        assert_eq!(
+            res.as_mut().unwrap().get_one::<usize>("unified").unwrap(),
+            &5
+        );
+        assert_eq!(
+            res.as_mut()
+                .unwrap()
+                .get_one::<String>("diff-algorithm")
+                .unwrap(),
+            "patience"
+        );
+        assert!(res.as_mut().unwrap().get_flag("name-status"));
+
+        // Not exepected algorithm
+        let res = cmd.try_get_matches_from_mut(vec![
+            "diff",
+            "--diff-algorithm",
+            "fast",
+            "jfaf7GATG7ya",
+            "6fawfwK234412",
+        ]);
+        assert!(res.is_err());
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::InvalidValue);
     }
 
     #[test]
@@ -604,5 +1067,33 @@ mod tests {
             .unwrap()
             .try_get_one::<String>("value")
             .is_err());
+
+        // Create a bookmark and go to it
+        let mut res = cmd.try_get_matches_from_mut(vec!["go-to", "-c", "feature/login"]);
+        assert!(res.is_ok());
+        assert_eq!(
+            res.as_mut().unwrap().get_one::<String>("create").unwrap(),
+            "feature/login"
+        );
+        assert!(res.as_mut().unwrap().get_one::<String>("hash").is_none());
+
+        let mut res =
+            cmd.try_get_matches_from_mut(vec!["go-to", "--create", "feature/login", "HEAD~1"]);
+        assert!(res.is_ok());
+        assert_eq!(
+            res.as_mut().unwrap().get_one::<String>("hash").unwrap(),
+            "HEAD~1"
+        );
+
+        // Treat local changes
+        let mut res = cmd.try_get_matches_from_mut(vec!["go-to", "-f", "jfaf7GATG7ya"]);
+        assert!(res.is_ok());
+        assert!(res.as_mut().unwrap().get_flag("force"));
+        assert!(!res.as_mut().unwrap().get_flag("merge"));
+        let mut res = cmd.try_get_matches_from_mut(vec!["go-to", "--merge", "jfaf7GATG7ya"]);
+        assert!(res.is_ok());
+        assert!(res.as_mut().unwrap().get_flag("merge"));
+        let res = cmd.try_get_matches_from_mut(vec!["go-to", "-f", "-m", "jfaf7GATG7ya"]);
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::ArgumentConflict);
     }
 }
@@ -4,15 +4,19 @@ use std::path::PathBuf;
 
 // External
 use anyhow::{bail, Result};
+use regex::Regex;
 
 // Internal
 mod cli;
 mod subcommand;
+mod util;
 
 use cli::*;
 use nss_core::nss_io::file_system;
 use nss_core::repo::repository::NssRepository;
 use subcommand::*;
+use util::date;
+use util::walk::Order;
 
 /// Parse argument and run commnad  
 fn main() -> Result<()> {
@@ -98,51 +102,146 @@ fn main() -> Result<()> {
                     }
                 },
 
+                Some(("status", _)) => status::run(&NssRepository::new(repo_path))?,
+
                 Some(("reg", sub_m)) => {
                     let message: &String = sub_m.get_one("message").unwrap();
                     reg::run(&NssRepository::new(repo_path), message)?
                 }
 
                 Some(("bookmark", sub_m)) => {
-                    let book_name: &String = sub_m.get_one("bookmarker").unwrap();
+                    let book_name: Option<&String> = sub_m.get_one("bookmarker");
                     let hash: Option<&String> = sub_m.get_one("hash");
-                    if sub_m.get_flag("replace") {
-                        bookmark::run_option_r(
-                            &NssRepository::new(repo_path),
-                            book_name,
-                            hash.unwrap(),
-                        )?
-                    } else {
-                        bookmark::run(&NssRepository::new(repo_path), book_name, hash)?
+                    let repository = NssRepository::new(repo_path);
+                    match book_name {
+                        None => bookmark::run_option_l(&repository)?,
+                        Some(b) if sub_m.get_flag("delete") => {
+                            bookmark::run_option_d(&repository, b, sub_m.get_flag("force"))?
+                        }
+                        Some(b) if sub_m.get_flag("move") => {
+                            bookmark::run_option_m(&repository, b, hash.unwrap())?
+                        }
+                        Some(b) if sub_m.get_flag("replace") => bookmark::run_option_r(
+                            &repository,
+                            b,
+                            hash.map(|h| h.as_str()).unwrap_or("HEAD"),
+                        )?,
+                        Some(b) => bookmark::run(&repository, b, hash)?,
                     }
                 }
 
                 Some(("update-ref", sub_m)) => {
                     let new_commit: Option<&String> = sub_m.get_one("hash");
-                    update_ref::run(&NssRepository::new(repo_path), new_commit.unwrap())?
+                    let old_commit: Option<&String> = sub_m.get_one("old-hash");
+                    let old_commit = old_commit.map(|h| h.as_str());
+                    match sub_m.get_one::<String>("bookmark") {
+                        Some(b) => update_ref::run_option_b(
+                            &NssRepository::new(repo_path),
+                            b,
+                            new_commit.unwrap(),
+                            old_commit,
+                        )?,
+                        None => update_ref::run(
+                            &NssRepository::new(repo_path),
+                            new_commit.unwrap(),
+                            old_commit,
+                        )?,
+                    }
+                }
+
+                Some(("reflog", sub_m)) => {
+                    let reference: Option<&String> = sub_m.get_one("reference");
+                    reflog::run(&NssRepository::new(repo_path), reference)?
                 }
 
                 Some(("story", sub_m)) => {
-                    if sub_m.get_flag("short") {
-                        history::run_option_s(&NssRepository::new(repo_path))?
+                    let graph = sub_m.get_flag("graph");
+                    let order = match sub_m.get_flag("topo-order") || graph {
+                        true => Order::Topo,
+                        false => Order::Date,
+                    };
+                    let current_dir = env::current_dir()?;
+                    let option = history::StoryOption {
+                        order,
+                        graph,
+                        range: sub_m.get_one::<String>("revision").cloned(),
+                        max_count: sub_m.get_one::<usize>("max-count").copied(),
+                        since: sub_m
+                            .get_one::<String>("since")
+                            .map(|d| date::parse_timestamp(d))
+                            .transpose()?,
+                        until: sub_m
+                            .get_one::<String>("until")
+                            .map(|d| date::parse_timestamp(d))
+                            .transpose()?,
+                        author: sub_m
+                            .get_one::<String>("author")
+                            .map(|p| Regex::new(p))
+                            .transpose()?,
+                        grep: sub_m
+                            .get_one::<String>("grep")
+                            .map(|p| Regex::new(p))
+                            .transpose()?,
+                        paths: sub_m
+                            .get_many::<PathBuf>("paths")
+                            .unwrap_or_default()
+                            .map(|p| {
+                                current_dir
+                                    .join(p)
+                                    .strip_prefix(&repo_path)
+                                    .map(|p| p.to_path_buf())
+                            })
+                            .collect::<Result<Vec<PathBuf>, _>>()?,
+                    };
+                    if let Some(format) = sub_m.get_one::<String>("format") {
+                        history::run_option_format(&NssRepository::new(repo_path), &option, format)?
+                    } else if sub_m.get_flag("short") {
+                        history::run_option_s(&NssRepository::new(repo_path), &option)?
                     } else {
-                        history::run(&NssRepository::new(repo_path))?
+                        history::run(&NssRepository::new(repo_path), &option)?
                     }
                 }
 
                 Some(("go-to", sub_m)) => {
                     let target: Option<&String> = sub_m.get_one("hash");
-                    go_to::run(&NssRepository::new(repo_path), target.unwrap())?
+                    let local = match (sub_m.get_flag("force"), sub_m.get_flag("merge")) {
+                        (true, _) => go_to::LocalChanges::Discard,
+                        (_, true) => go_to::LocalChanges::Merge,
+                        _ => go_to::LocalChanges::Keep,
+                    };
+                    match sub_m.get_one::<String>("create") {
+                        Some(b) => {
+                            go_to::run_option_c(&NssRepository::new(repo_path), b, target, local)?
+                        }
+                        None => go_to::run(&NssRepository::new(repo_path), target.unwrap(), local)?,
+                    }
+                }
+
+                Some(("merge", sub_m)) => {
+                    let bookmarker: &String = sub_m.get_one("bookmarker").unwrap();
+                    merge::run(&NssRepository::new(repo_path), bookmarker)?
                 }
 
                 Some(("diff", sub_m)) => {
                     let target: Option<&String> = sub_m.get_one("target-hash");
                     let another: Option<&String> = sub_m.get_one("another-hash");
-                    diff::run(
-                        &NssRepository::new(repo_path),
-                        target.unwrap(),
-                        another.unwrap(),
-                    )?
+                    let option = diff::DiffOption {
+                        algorithm: sub_m.get_one::<String>("diff-algorithm").unwrap().parse()?,
+                        context: *sub_m.get_one::<usize>("unified").unwrap(),
+                        name_status: sub_m.get_flag("name-status"),
+                    };
+                    let (target, another) = match (sub_m.get_flag("staged"), target, another) {
+                        (true, Some(t), _) => (diff::Target::Commit(t), diff::Target::Index),
+                        (true, None, _) => (diff::Target::Head, diff::Target::Index),
+                        (false, Some(t), Some(a)) => {
+                            (diff::Target::Commit(t), diff::Target::Commit(a))
+                        }
+                        (false, Some(t), None) => {
+                            (diff::Target::Commit(t), diff::Target::WorkingTree)
+                        }
+                        (false, None, _) => (diff::Target::Index, diff::Target::WorkingTree),
+                    };
+                    diff::run(&NssRepository::new(repo_path), target, another, option)?
                 }
 
                 Some(("debug", _sub_m)) => {
@@ -4,9 +4,12 @@ pub mod go_to;
 pub mod hasher;
 pub mod history;
 pub mod lk_snap;
+pub mod merge;
 pub mod ocat;
+pub mod reflog;
 pub mod reg;
 pub mod snap;
+pub mod status;
 pub mod up_snap;
 pub mod update_ref;
 pub mod voyage;
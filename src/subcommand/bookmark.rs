@@ -3,16 +3,20 @@
 //! A bookmark is a special tool that allows you to refer to
 //! a specific commit. You can easily go back to the book
 //! (change history) that you have carefully built up.
-
-// Std
-use std::fs;
-use std::fs::{File, OpenOptions};
-use std::io::prelude::*;
+//!
+//! Bookmarks are stored under `.nss/bookmarks/local`, and a
+//! hierarchical name like `feature/login` is a nested directory.
 
 // External
-use anyhow::{bail, Context, Result};
+use anyhow::{bail, Result};
+use colored::*;
 
 // Internal
+use crate::util::head::Head;
+use crate::util::pretty::ABBREV_LEN;
+use crate::util::refs::{self, Expected, RefTransaction};
+use crate::util::walk::{self, CommitNode};
+use crate::util::{reflog, revision};
 use nss_core::repository::NssRepository;
 
 /// Create a new bookmarker to argument commit hash.
@@ -20,58 +24,336 @@ use nss_core::repository::NssRepository;
 /// **Note:** If you do not specify a hash, it refers to the
 /// value pointed to by HEAD.
 pub fn run(repository: &NssRepository, book_name: &str, hash: Option<&String>) -> Result<()> {
-    match hash {
-        Some(v) => {
-            let object = repository.read_object(v)?;
-            if object.as_str() == "commit" {
-                let mut file = OpenOptions::new()
-                    .create_new(true)
-                    .write(true)
-                    .open(repository.bookmarks_path(book_name))
-                    .with_context(|| format!("{} already exits", book_name))?;
-
-                file.write_all(v.as_bytes())?;
-            } else {
-                bail!("Not commit hash ({})", v)
-            }
-        }
-        _ => {
-            let head_hash = fs::read_to_string(repository.head_path())?;
-            let mut file = File::create(repository.bookmarks_path(book_name))?;
-            file.write_all(head_hash.as_bytes())?
-        }
+    let expr = hash.map(|h| h.as_str()).unwrap_or("HEAD");
+    let hash = revision::resolve_commit(repository, expr)?;
+
+    refs::check_bookmarker(book_name)?;
+    if repository.bookmarks_path(book_name).exists() {
+        bail!("{} already exits", book_name)
     }
+
+    RefTransaction::new(repository, &format!("bookmark: Created from {}", expr))
+        .update(&refs::local_path(book_name), Expected::Empty, &hash)
+        .commit()?;
+
     Ok(())
 }
 
 /// Change the pointer of existing bookmarks.
+pub fn run_option_r(repository: &NssRepository, bookmarker: &str, hash: &str) -> Result<()> {
+    let reason = format!("bookmark -r: Moved to {}", hash);
+    let old = read_existing(repository, bookmarker)?;
+    let hash = revision::resolve_commit(repository, hash)?;
+
+    RefTransaction::new(repository, &reason)
+        .update(&refs::local_path(bookmarker), Expected::from(old), &hash)
+        .commit()?;
+
+    Ok(())
+}
+
+/// Bookmark shown in the list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ListItem {
+    name: String,
+    hash: String,
+    subject: String,
+    /// HEAD is on it
+    current: bool,
+}
+
+/// List all local bookmarks with the tip commit, marking the current one.
+pub fn run_option_l(repository: &NssRepository) -> Result<()> {
+    let items = list(repository)?;
+    let width = items.iter().map(|i| i.name.len()).max().unwrap_or(0);
+
+    if let Head::Detached(hash) = Head::read(repository)? {
+        println!(
+            "{} (HEAD detached at {})",
+            "*".green(),
+            &hash[0..ABBREV_LEN]
+        );
+    }
+    for item in &items {
+        let name = format!("{:width$}", item.name, width = width);
+        match item.current {
+            true => print!("{} {}", "*".green(), name.green()),
+            false => print!("  {}", name),
+        }
+        println!(" {} {}", &item.hash[0..ABBREV_LEN].yellow(), item.subject);
+    }
+
+    Ok(())
+}
+
+/// Local bookmarks having a commit, sorted by name.
+fn list(repository: &NssRepository) -> Result<Vec<ListItem>> {
+    let head = Head::read(repository)?;
+
+    refs::all_references(repository)?
+        .into_iter()
+        .filter(|r| r.path.starts_with(refs::LOCAL_PREFIX))
+        .map(|bookmark| {
+            let node = CommitNode::read(repository, &bookmark.hash)?;
+            Ok(ListItem {
+                name: bookmark.short_name().to_string(),
+                subject: node
+                    .commit
+                    .message
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+                    .to_string(),
+                current: head.bookmarker() == Some(bookmark.short_name()),
+                hash: bookmark.hash,
+            })
+        })
+        .collect()
+}
+
+/// Delete the bookmark and its reflog.
 ///
-/// **Note:** If you do not specify a hash, it refers to the
-/// value pointed to by HEAD.
-pub fn run_option_r(repository: &NssRepository, bookmarker: &str, hash: &String) -> Result<()> {
-    let mut file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .open(repository.bookmarks_path(bookmarker))
-        .with_context(|| format!("No such bookmarker: {}", bookmarker))?;
-
-    let object = repository.read_object(hash)?;
-    if object.as_str() == "commit" {
-        file.write_all(hash.as_bytes())?;
-    } else {
-        bail!("Not commit hash ({})", hash)
+/// **Note:** The bookmark not merged into HEAD is kept unless `force`,
+/// because its commits may be lost.
+pub fn run_option_d(repository: &NssRepository, bookmarker: &str, force: bool) -> Result<()> {
+    let hash = read_existing(repository, bookmarker)?;
+    if Head::read(repository)?.bookmarker() == Some(bookmarker) {
+        bail!("Cannot delete bookmark {} which you are on", bookmarker)
+    }
+
+    if let (Some(hash), false) = (&hash, force) {
+        let merged = match revision::head_hash(repository)? {
+            Some(head) => walk::ancestors(repository, &head)?.contains(hash),
+            None => false,
+        };
+        if !merged {
+            bail!(
+                "{} is not merged into HEAD\nIf you are sure to delete it, run with --force",
+                bookmarker
+            )
+        }
+    }
+
+    let path = refs::local_path(bookmarker);
+    RefTransaction::new(repository, "bookmark -d")
+        .delete(&path, Expected::from(hash.clone()))
+        .commit()?;
+    reflog::remove(repository, &path)?;
+
+    match hash {
+        Some(hash) => println!(
+            "Deleted bookmark {} (was {})",
+            bookmarker,
+            &hash[0..ABBREV_LEN]
+        ),
+        None => println!("Deleted bookmark {}", bookmarker),
+    }
+
+    Ok(())
+}
+
+/// Rename the bookmark with its reflog, moving HEAD if it pointed there.
+pub fn run_option_m(repository: &NssRepository, old_name: &str, new_name: &str) -> Result<()> {
+    let hash = read_existing(repository, old_name)?;
+    refs::check_bookmarker(new_name)?;
+    if repository.bookmarks_path(new_name).exists() {
+        bail!("{} already exits", new_name)
+    }
+
+    let old_path = refs::local_path(old_name);
+    let new_path = refs::local_path(new_name);
+    let reason = format!("bookmark -m: Renamed {} to {}", old_name, new_name);
+    let mut transaction = RefTransaction::new(repository, &reason)
+        .delete(&old_path, Expected::from(hash.clone()))
+        .update(
+            &new_path,
+            Expected::Empty,
+            hash.as_deref().unwrap_or_default(),
+        );
+    if Head::read(repository)?.bookmarker() == Some(old_name) {
+        transaction = transaction.update(refs::HEAD, Expected::Value(old_path.clone()), &new_path);
     }
+    transaction.commit()?;
+    reflog::rename(repository, &old_path, &new_path)?;
 
     Ok(())
 }
 
+/// Commit of the existing bookmark (`None` if it has no commit yet).
+fn read_existing(repository: &NssRepository, bookmarker: &str) -> Result<Option<String>> {
+    refs::check_bookmarker(bookmarker)?;
+    if !repository.bookmarks_path(bookmarker).is_file() {
+        bail!("No such bookmarker: {}", bookmarker)
+    }
+
+    revision::read_bookmark(repository, bookmarker)
+}
+
 #[cfg(test)]
 mod tests {
-    // use super::*;
+    use super::*;
+    use crate::subcommand::voyage;
+    use crate::util::fixture::write_commit;
+    use std::fs;
+    use testdir::testdir;
 
     #[test]
     fn test_run() {}
 
     #[test]
-    fn test_run_option_r() {}
+    fn test_run_option_r() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        let first = write_commit(&test_repo, &[], "first");
+        let second = write_commit(&test_repo, &[&first], "second");
+        fs::write(test_repo.bookmarks_path("voyage"), &first).unwrap();
+
+        run_option_r(&test_repo, "voyage", &second).unwrap();
+        assert_eq!(
+            revision::read_bookmark(&test_repo, "voyage").unwrap(),
+            Some(second.clone())
+        );
+
+        // Only an existing bookmark with a valid name
+        assert!(run_option_r(&test_repo, "topic", &first).is_err());
+        assert!(!test_repo.bookmarks_path("topic").exists());
+        assert!(run_option_r(&test_repo, "../../config", &first).is_err());
+        assert!(run_option_r(&test_repo, "../../HEAD", &first).is_err());
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_option_l() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        let first = write_commit(&test_repo, &[], "first\n\nbody");
+        let second = write_commit(&test_repo, &[&first], "second");
+        fs::write(test_repo.bookmarks_path("voyage"), &second).unwrap();
+        run(&test_repo, "topic", Some(&first)).unwrap();
+        run(&test_repo, "feature/login", None).unwrap();
+
+        let items = list(&test_repo).unwrap();
+        let names = items.iter().map(|i| i.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["feature/login", "topic", "voyage"]);
+        assert_eq!(
+            items.iter().map(|i| i.current).collect::<Vec<_>>(),
+            vec![false, false, true]
+        );
+        assert_eq!(items[0].hash, second);
+        assert_eq!(items[1].subject, "first");
+        assert!(run_option_l(&test_repo).is_ok());
+
+        // Detached HEAD is on no bookmark
+        fs::write(
+            test_repo.head_path(),
+            Head::Detached(first.clone()).to_content(),
+        )
+        .unwrap();
+        assert!(list(&test_repo).unwrap().iter().all(|i| !i.current));
+        assert!(run_option_l(&test_repo).is_ok());
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_option_d() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        //   first - second (voyage)
+        //        \
+        //         side
+        let first = write_commit(&test_repo, &[], "first");
+        let second = write_commit(&test_repo, &[&first], "second");
+        let side = write_commit(&test_repo, &[&first], "side");
+        fs::write(test_repo.bookmarks_path("voyage"), &second).unwrap();
+        run(&test_repo, "merged", Some(&first)).unwrap();
+        run(&test_repo, "side", Some(&side)).unwrap();
+
+        // Merged into HEAD
+        run_option_d(&test_repo, "merged", false).unwrap();
+        assert!(!test_repo.bookmarks_path("merged").exists());
+        assert!(reflog::read(&test_repo, &refs::local_path("merged"))
+            .unwrap()
+            .is_empty());
+
+        // Not merged is kept unless forced
+        assert!(run_option_d(&test_repo, "side", false).is_err());
+        assert!(test_repo.bookmarks_path("side").exists());
+        run_option_d(&test_repo, "side", true).unwrap();
+        assert!(!test_repo.bookmarks_path("side").exists());
+
+        // Current, missing and invalid bookmarks
+        assert!(run_option_d(&test_repo, "voyage", true).is_err());
+        assert!(run_option_d(&test_repo, "ghost", true).is_err());
+        assert!(run_option_d(&test_repo, "../../config", true).is_err());
+        assert!(temp_dir.join(".nss").join("config").exists());
+        assert!(run_option_d(&test_repo, "../../HEAD", true).is_err());
+        assert!(test_repo.head_path().exists());
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_option_m() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        let first = write_commit(&test_repo, &[], "first");
+        let second = write_commit(&test_repo, &[&first], "second");
+        let voyage = refs::local_path("voyage");
+        for (old, new) in [(None, &first), (Some(first.clone()), &second)] {
+            RefTransaction::new(&test_repo, "reg")
+                .update(&voyage, Expected::from(old), new)
+                .commit()
+                .unwrap();
+        }
+        run(&test_repo, "topic", Some(&first)).unwrap();
+
+        // HEAD and the reflog move with the bookmark
+        run_option_m(&test_repo, "voyage", "feature/login").unwrap();
+        let login = refs::local_path("feature/login");
+        assert!(!test_repo.bookmarks_path("voyage").exists());
+        assert_eq!(
+            revision::read_bookmark(&test_repo, "feature/login").unwrap(),
+            Some(second.clone())
+        );
+        assert_eq!(
+            Head::read(&test_repo).unwrap(),
+            Head::Symbolic(login.clone())
+        );
+        assert!(reflog::read(&test_repo, &voyage).unwrap().is_empty());
+        let entries = reflog::read(&test_repo, &login).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert!(entries[0].reason.starts_with("bookmark -m"));
+        assert_eq!(entries[2].new, first);
+
+        // Existing, missing and invalid names
+        assert!(run_option_m(&test_repo, "topic", "feature/login").is_err());
+        assert!(run_option_m(&test_repo, "ghost", "other").is_err());
+        assert!(run_option_m(&test_repo, "../../config", "other").is_err());
+        assert!(run_option_m(&test_repo, "topic", "../HEAD").is_err());
+        assert!(test_repo.bookmarks_path("topic").exists());
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
 }
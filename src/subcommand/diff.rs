@@ -1,26 +1,130 @@
+//! **Diff command** ... Base command: `git diff`
+//!
+//! Show changes between commits, the index and the working directory,
+//! file by file and line by line.
+
+// Std
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
 // External
 use anyhow::{bail, Result};
+use colored::*;
 
 // Internal
+use crate::util::index::{read_index, Extensions};
+use crate::util::line_diff::{self, Algorithm};
+use crate::util::revision::{self, head_hash};
+use crate::util::tree;
 use nss_core::repository::NssRepository;
-use nss_core::struct_set::{DIffTag, Diff, Index, Object, Tree};
+use nss_core::struct_set::{DIffTag, Index, Object};
+
+/// One side of the comparison.
+#[derive(Debug, Clone, Copy)]
+pub enum Target<'a> {
+    /// Files in the working directory (only tracked files)
+    WorkingTree,
+    /// Snapshot in the index
+    Index,
+    /// Commit pointed by HEAD (empty before the first reg)
+    Head,
+    /// Specified commit
+    Commit(&'a str),
+}
+
+/// How to display the difference of each file.
+#[derive(Debug, Clone, Copy)]
+pub struct DiffOption {
+    pub algorithm: Algorithm,
+    pub context: usize,
+    pub name_status: bool,
+}
 
-pub fn run(repository: &NssRepository, target: &str, another: &str) -> Result<()> {
+impl Default for DiffOption {
+    fn default() -> Self {
+        Self {
+            algorithm: Algorithm::default(),
+            context: 3,
+            name_status: false,
+        }
+    }
+}
+
+/// Compare `target` (old side) with `another` (new side).
+///
+/// **Note:** The working directory is expected as `another`.
+pub fn run(
+    repository: &NssRepository,
+    target: Target,
+    another: Target,
+    option: DiffOption,
+) -> Result<()> {
     // Get target index
-    let tree = to_base_tree(repository, target)?;
-    let target_index = Index::try_from_tree(repository, tree)?;
+    let target_index = load_index(repository, target)?;
+    let target_hashes = hash_map(&target_index);
 
     // Get another index
-    let tree = to_base_tree(repository, another)?;
-    let another_index = Index::try_from_tree(repository, tree)?;
+    let another_index = load_index(repository, another)?;
+    let another_hashes = hash_map(&another_index);
+
+    // Untracked files are not the subject of the difference
+    let tracked = match another {
+        Target::WorkingTree => Some(
+            load_index(repository, Target::Index)?
+                .filemetas
+                .into_iter()
+                .map(|f| f.filename)
+                .collect::<HashSet<PathBuf>>(),
+        ),
+        _ => None,
+    };
+
+    // Index keeps our version of the unmerged path
+    let unmerged = match (target, another) {
+        (Target::Index, _) | (_, Target::Index) => Extensions::read(repository)?
+            .conflicts()?
+            .into_iter()
+            .map(|c| c.path)
+            .collect::<HashSet<PathBuf>>(),
+        _ => HashSet::new(),
+    };
 
     let diff = target_index.diff(another_index);
 
     for (difftype, filename) in diff {
+        if let (DIffTag::Insert, Some(tracked)) = (&difftype, &tracked) {
+            if !tracked.contains(&filename) {
+                continue;
+            }
+        }
+
+        if option.name_status {
+            match difftype {
+                DIffTag::Delete => println!("D: {}", filename.to_str().unwrap()),
+                DIffTag::Insert => println!("U: {}", filename.to_str().unwrap()),
+                DIffTag::Replace => println!("M: {}", filename.to_str().unwrap()),
+                _ => (),
+            }
+            continue;
+        }
+
+        if unmerged.contains(&filename) {
+            println!("* Unmerged path {}", filename.display());
+        }
+
         match difftype {
-            DIffTag::Delete => println!("D: {}", filename.to_str().unwrap()),
-            DIffTag::Insert => println!("U: {}", filename.to_str().unwrap()),
-            DIffTag::Replace => println!("M: {}", filename.to_str().unwrap()),
+            DIffTag::Delete | DIffTag::Insert | DIffTag::Replace => {
+                let old =
+                    read_content(repository, target, target_hashes.get(&filename), &filename)?;
+                let new = read_content(
+                    repository,
+                    another,
+                    another_hashes.get(&filename),
+                    &filename,
+                )?;
+                print_file_diff(&filename, old, new, option)?
+            }
             _ => (),
         }
     }
@@ -28,15 +132,99 @@ pub fn run(repository: &NssRepository, target: &str, another: &str) -> Result<()
     Ok(())
 }
 
-fn to_base_tree(repository: &NssRepository, target: &str) -> Result<Tree> {
-    let commit = match repository.read_object(target)? {
-        Object::Commit(c) => c,
-        _ => bail!("{} is not commit hash", target),
+fn load_index(repository: &NssRepository, target: Target) -> Result<Index> {
+    match target {
+        Target::WorkingTree => Index::new_all(repository),
+        Target::Index => read_index(repository),
+        Target::Head => match head_hash(repository)? {
+            Some(hash) => tree::to_index(repository, &hash),
+            None => Ok(Index::empty()),
+        },
+        Target::Commit(expr) => {
+            tree::to_index(repository, &revision::resolve_commit(repository, expr)?)
+        }
+    }
+}
+
+/// Content of the file on the side, `None` if the file does not exist there.
+fn read_content(
+    repository: &NssRepository,
+    target: Target,
+    hash: Option<&String>,
+    filename: &Path,
+) -> Result<Option<Vec<u8>>> {
+    let Some(hash) = hash else {
+        return Ok(None);
+    };
+
+    match target {
+        Target::WorkingTree => Ok(Some(fs::read(repository.path().join(filename))?)),
+        _ => match repository.read_object(hash)? {
+            Object::Blob(blob) => Ok(Some(blob.content)),
+            _ => bail!("{} is not blob hash", hash),
+        },
+    }
+}
+
+/// Display the unified diff of one file.
+/// `None` means the file does not exist on that side.
+fn print_file_diff(
+    filename: &Path,
+    old: Option<Vec<u8>>,
+    new: Option<Vec<u8>>,
+    option: DiffOption,
+) -> Result<()> {
+    let name = filename.display();
+    println!("{}", format!("diff --nss a/{} b/{}", name, name).bold());
+    match (&old, &new) {
+        (None, _) => println!("{}", "new file".bold()),
+        (_, None) => println!("{}", "deleted file".bold()),
+        _ => (),
+    }
+
+    let old_label = match old {
+        Some(_) => format!("a/{}", name),
+        None => "/dev/null".to_string(),
     };
+    let new_label = match new {
+        Some(_) => format!("b/{}", name),
+        None => "/dev/null".to_string(),
+    };
+    let old = old.unwrap_or_default();
+    let new = new.unwrap_or_default();
+
+    if line_diff::is_binary(&old) || line_diff::is_binary(&new) {
+        println!("Binary files differ");
+        return Ok(());
+    }
+
+    println!("{}", format!("--- {}\n+++ {}", old_label, new_label).bold());
+
+    let old = String::from_utf8_lossy(&old);
+    let new = String::from_utf8_lossy(&new);
+    let lines = line_diff::unified(
+        &line_diff::split_lines(&old),
+        &line_diff::split_lines(&new),
+        option.algorithm,
+        option.context,
+    );
 
-    // target commit hash needs to have tree hash
-    match repository.read_object(&commit.tree_hash)? {
-        Object::Tree(t) => Ok(t),
-        _ => bail!("{} is not tree hash", &commit.tree_hash),
+    for line in lines {
+        match line.chars().next() {
+            Some('@') => println!("{}", line.cyan()),
+            Some('-') => println!("{}", line.red()),
+            Some('+') => println!("{}", line.green()),
+            _ => println!("{}", line),
+        }
     }
+
+    Ok(())
+}
+
+fn hash_map(index: &Index) -> HashMap<PathBuf, String> {
+    index
+        .filemetas
+        .iter()
+        .map(|f| (f.filename.clone(), hex::encode(&f.hash)))
+        .collect()
 }
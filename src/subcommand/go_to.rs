@@ -2,96 +2,463 @@
 //!
 //! Update the working directory and index based on
 //! the specified commit.
+//!
+//! Local changes which would be overwritten stop the command,
+//! unless they are discarded (`--force`) or merged (`--merge`).
+
+// Std
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
 
 // External
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
+use colored::*;
 
 // Internal
-use nss_core::nss_io::file_system;
+use super::status;
+use crate::util::head::Head;
+use crate::util::index::{write_index, write_index_with, Extensions};
+use crate::util::refs::{self, Expected, RefTransaction};
+use crate::util::worktree::{self, Content, Update};
+use crate::util::{object, revision, three_way, tree};
 use nss_core::repository::NssRepository;
-use nss_core::struct_set::{Index, Object, Tree};
-
-// TODO: when delete or create , use tempolary dir
-pub fn run(repository: &NssRepository, target: &str) -> Result<()> {
-    // Get target index
-    let tree = to_base_tree(repository, target)?;
-    let target_index = Index::try_from_tree(repository, tree)?;
-
-    // Clear working directory by reference HEAD Index
-    let head_index = repository.read_index()?;
-    delete_file(repository, &head_index)?;
-
-    // restoration by tree
-    match create_file(repository, &target_index) {
-        Ok(_) => {
-            // Target index -> HEAD Index
-            repository.write_index(target_index)?;
-        }
-        Err(e) => {
-            // Rollback
-            create_file(repository, &head_index)?;
-            bail!("{}\nCan't go to {}", e, target)
-        }
-    }
+use nss_core::struct_set::{DIffTag, FileMeta, Index};
 
-    repository.write_head(target)?;
+/// Go to the commit. HEAD is put on the bookmark if the target is
+/// a bookmarker, and detached at the commit otherwise.
+pub fn run(repository: &NssRepository, target: &str, local: LocalChanges) -> Result<()> {
+    let reason = format!("go-to: Moved to {}", target);
+    let head = Head::read(repository)?;
+    let commit = revision::resolve_commit(repository, target)?;
+    let new_head = match repository.bookmarks_path(target).is_file() {
+        true => Head::Symbolic(refs::local_path(target)),
+        false => Head::Detached(commit.clone()),
+    };
+
+    let transaction = RefTransaction::new(repository, &reason).update(
+        refs::HEAD,
+        Expected::Value(head.value().to_string()),
+        new_head.value(),
+    );
+    checkout(repository, &commit, local, transaction)?;
+
+    print_head(&new_head, &commit);
 
     Ok(())
 }
 
-fn to_base_tree(repository: &NssRepository, target: &str) -> Result<Tree> {
-    let commit = match repository.read_object(target)? {
-        Object::Commit(c) => c,
-        _ => bail!("{} is not commit hash", target),
-    };
+/// Create a new bookmark at the start commit (HEAD by default) and go to it.
+pub fn run_option_c(
+    repository: &NssRepository,
+    bookmarker: &str,
+    start: Option<&String>,
+    local: LocalChanges,
+) -> Result<()> {
+    let start = start.map(|s| s.as_str()).unwrap_or(refs::HEAD);
+    let reason = format!("go-to -c: Created {} from {}", bookmarker, start);
+    let head = Head::read(repository)?;
+    let commit = revision::resolve_commit(repository, start)?;
 
-    // target commit hash needs to have tree hash
-    match repository.read_object(&commit.tree_hash)? {
-        Object::Tree(t) => Ok(t),
-        _ => bail!("{} is not tree hash", &commit.tree_hash),
+    refs::check_bookmarker(bookmarker)?;
+    if repository.bookmarks_path(bookmarker).exists() {
+        bail!("{} already exits", bookmarker)
     }
+    let new_head = Head::Symbolic(refs::local_path(bookmarker));
+
+    // Created and switched to at once
+    let transaction = RefTransaction::new(repository, &reason)
+        .update(new_head.value(), Expected::Empty, &commit)
+        .update(
+            refs::HEAD,
+            Expected::Value(head.value().to_string()),
+            new_head.value(),
+        );
+    checkout(repository, &commit, local, transaction)?;
+
+    print_head(&new_head, &commit);
+
+    Ok(())
+}
+
+fn print_head(new_head: &Head, commit: &str) {
+    match new_head.bookmarker() {
+        Some(bookmarker) => println!("On bookmark {}", bookmarker.bright_green()),
+        None => println!(
+            "HEAD is now detached at {}. Leave a bookmark to reg on it.",
+            &commit[0..7].yellow()
+        ),
+    }
+}
+
+/// How local changes are treated when going to another commit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocalChanges {
+    /// Refuse to go if they would be overwritten
+    Keep,
+    /// Overwrite them (`--force`)
+    Discard,
+    /// Merge them into the files of the commit (`--merge`)
+    Merge,
 }
 
-fn delete_file(repository: &NssRepository, index: &Index) -> Result<()> {
-    for path in index.filemetas.iter() {
-        match file_system::remove_file(repository.path().join(&path.filename)) {
-            Ok(..) => (),
-            Err(e) => match e.kind() {
-                std::io::ErrorKind::NotFound => (),
-                _ => bail!("Unexpected error occurred while deleting the file！"),
-            },
+/// Replace the working directory and index with the commit snapshot,
+/// and then move the references by the transaction (e.g. HEAD).
+///
+/// **Note:** Only the files differing between the index and the commit are
+/// deleted or rewritten, through a temporary directory (see [`worktree`]),
+/// so nothing is changed if it fails on the way, including the transaction
+/// (a reference moved by another process).
+pub fn checkout(
+    repository: &NssRepository,
+    target: &str,
+    local: LocalChanges,
+    transaction: RefTransaction,
+) -> Result<()> {
+    let mut target_index = tree::to_index(repository, target)?;
+    // Put back with the files if the transaction fails
+    let extensions = Extensions::read(repository)?;
+    let current_index = repository.read_index()?;
+    let previous_index = current_index.clone();
+    let target_files = hash_map(&target_index);
+    let current_metas = current_index
+        .filemetas
+        .iter()
+        .map(|f| (f.filename.clone(), f.clone()))
+        .collect::<HashMap<PathBuf, FileMeta>>();
+
+    // Only the files differing from the target are touched
+    let mut updates = current_index
+        .diff(target_index.clone())
+        .into_iter()
+        .filter_map(|(tag, path)| match tag {
+            DIffTag::Insert | DIffTag::Replace => Some(Update {
+                content: target_files.get(&path).cloned().map(Content::Blob),
+                path,
+            }),
+            DIffTag::Delete => Some(Update {
+                content: None,
+                path,
+            }),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let updated = updates.iter().map(|u| &u.path).collect::<HashSet<_>>();
+
+    // Untouched files keep their entries, and so their stat
+    for filemeta in target_index.filemetas.iter_mut() {
+        if !updated.contains(&filemeta.filename) {
+            if let Some(current) = current_metas.get(&filemeta.filename) {
+                *filemeta = current.clone();
+            }
+        }
+    }
+
+    let status = status::collect(repository)?;
+    let untracked = status
+        .untracked
+        .iter()
+        .filter(|p| target_files.contains_key(*p))
+        .collect::<Vec<_>>();
+    if !untracked.is_empty() && local != LocalChanges::Discard {
+        bail!(
+            "The following untracked files would be overwritten by go-to:\n{}Please move or remove them, or go-to with --force.",
+            path_list(&untracked)
+        )
+    }
+
+    let changed = status
+        .staged
+        .iter()
+        .map(|(_, p)| p)
+        .chain(status.modified.iter())
+        .chain(status.deleted.iter())
+        .chain(status.unmerged.iter())
+        .filter(|p| updated.contains(p))
+        .collect::<BTreeSet<_>>();
+    let mut conflicts = vec![];
+    if !changed.is_empty() {
+        match local {
+            LocalChanges::Keep => bail!(
+                "Your local changes to the following files would be overwritten by go-to:\n{}Please snap and reg them, or go-to with --force or --merge.",
+                path_list(&changed.into_iter().collect::<Vec<_>>())
+            ),
+            LocalChanges::Discard => (),
+            LocalChanges::Merge => {
+                conflicts = merge_local(repository, target, &changed, &mut updates)?
+            }
         }
     }
 
+    // Extensions (cached tree) are dropped with the previous index
+    worktree::apply(repository, &updates, || {
+        write_index(repository, target_index)?;
+        transaction.commit().or_else(|e| {
+            write_index_with(repository, previous_index, &extensions)?;
+            Err(e)
+        })
+    })
+    .with_context(|| format!("Can't go to {}", target))?;
+
+    for path in conflicts {
+        println!(
+            "{}: {}",
+            "CONFLICT (local changes)".red().bold(),
+            path.display()
+        );
+    }
+
     Ok(())
 }
 
-fn create_file(repository: &NssRepository, index: &Index) -> Result<()> {
-    let prefix = repository.path();
+/// Merge the local changes with the changes from HEAD to the target,
+/// and return the paths having conflicts.
+///
+/// **Note:** The merged files are left unsnapped.
+fn merge_local(
+    repository: &NssRepository,
+    target: &str,
+    changed: &BTreeSet<&PathBuf>,
+    updates: &mut [Update],
+) -> Result<Vec<PathBuf>> {
+    let head_files = match revision::head_hash(repository)? {
+        Some(hash) => hash_map(&tree::to_index(repository, &hash)?),
+        None => HashMap::new(),
+    };
 
-    for filemeta in index.filemetas.clone() {
-        let entry_hash = hex::encode(filemeta.hash);
+    let mut conflicts = vec![];
+    for update in updates.iter_mut().filter(|u| changed.contains(&u.path)) {
+        let base = head_files.get(&update.path);
+        let theirs = match &update.content {
+            Some(Content::Blob(hash)) => Some(hash),
+            _ => None,
+        };
+        let local = match fs::read(repository.path().join(&update.path)) {
+            Ok(content) => Some(content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
 
-        match repository.read_object(entry_hash)? {
-            Object::Blob(blob) => {
-                let path = prefix.join(filemeta.filename);
-                file_system::create_dir(path.parent().unwrap())?;
-                file_system::create_file_with_buffer(path, &blob.content)?;
-            }
-            _ => bail!("Index has tree object, so your commit is broken! (in go-to branch)"),
+        // Not changed by going, so the local version is kept
+        if theirs == base {
+            update.content = local.map(Content::Data);
+            continue;
+        }
+
+        let (Some(local), Some(theirs)) = (local, theirs) else {
+            bail!(
+                "Can't merge local changes to {}, which is deleted on one side",
+                update.path.display()
+            );
+        };
+        let base_content = match base {
+            Some(hash) => object::read_blob(repository, hash)?,
+            None => vec![],
+        };
+        let theirs_content = object::read_blob(repository, theirs)?;
+        let (Some(base_text), Some(local_text), Some(theirs_text)) = (
+            three_way::as_text(&base_content),
+            three_way::as_text(&local),
+            three_way::as_text(&theirs_content),
+        ) else {
+            bail!(
+                "Can't merge local changes to binary (or not UTF-8) file {}",
+                update.path.display()
+            );
         };
+
+        let result = three_way::merge3(base_text, local_text, theirs_text, "local", target);
+        if result.conflicts > 0 {
+            conflicts.push(update.path.clone());
+        }
+        update.content = Some(Content::Data(result.text.into_bytes()));
     }
 
-    Ok(())
+    Ok(conflicts)
+}
+
+fn hash_map(index: &Index) -> HashMap<PathBuf, String> {
+    index
+        .filemetas
+        .iter()
+        .map(|f| (f.filename.clone(), hex::encode(&f.hash)))
+        .collect()
+}
+
+fn path_list(paths: &[&PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|p| format!("\t{}\n", p.display()))
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
-    // use super::*;
+    use super::*;
+    use crate::subcommand::{reg, snap, voyage};
+    use crate::util::reflog;
+    use testdir::testdir;
 
     #[test]
     fn test_run() {}
 
+    #[test]
+    fn test_run_option_c() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        let file_path = temp_dir.join("first.rs");
+        fs::write(&file_path, "first").unwrap();
+        snap::shot_all(&test_repo).unwrap();
+        reg::run(&test_repo, "first").unwrap();
+        let first = revision::resolve(&test_repo, "HEAD").unwrap();
+        fs::write(&file_path, "second").unwrap();
+        snap::shot_all(&test_repo).unwrap();
+        reg::run(&test_repo, "second").unwrap();
+
+        run_option_c(
+            &test_repo,
+            "feature/login",
+            Some(&first),
+            LocalChanges::Keep,
+        )
+        .unwrap();
+        let login = refs::local_path("feature/login");
+        assert_eq!(
+            revision::read_bookmark(&test_repo, "feature/login").unwrap(),
+            Some(first.clone())
+        );
+        assert_eq!(
+            Head::read(&test_repo).unwrap(),
+            Head::Symbolic(login.clone())
+        );
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "first");
+        assert!(status::collect(&test_repo).unwrap().is_clean());
+
+        // Logged to the new bookmark and HEAD
+        for reference in [login.as_str(), refs::HEAD] {
+            let latest = reflog::read(&test_repo, reference).unwrap().remove(0);
+            assert_eq!(latest.new, first);
+            assert!(latest.reason.starts_with("go-to -c: Created feature/login"));
+        }
+        assert_eq!(reflog::read(&test_repo, &login).unwrap().len(), 1);
+
+        // Existing or invalid names change nothing
+        for name in [
+            "voyage",
+            "feature/login",
+            "../../config",
+            "bad name",
+            "topic.lock",
+        ] {
+            assert!(run_option_c(&test_repo, name, None, LocalChanges::Keep).is_err());
+        }
+        assert!(temp_dir.join(".nss").join("config").exists());
+        assert!(!test_repo.bookmarks_path("topic.lock").exists());
+        assert_eq!(Head::read(&test_repo).unwrap(), Head::Symbolic(login));
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "first");
+
+        // From HEAD by default
+        run_option_c(&test_repo, "topic", None, LocalChanges::Keep).unwrap();
+        assert_eq!(
+            revision::read_bookmark(&test_repo, "topic").unwrap(),
+            Some(first)
+        );
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_checkout() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        let file_path = temp_dir.join("first.rs");
+        fs::write(&file_path, "first").unwrap();
+        snap::shot_all(&test_repo).unwrap();
+        reg::run(&test_repo, "first").unwrap();
+        let first = revision::resolve(&test_repo, "HEAD").unwrap();
+        fs::write(&file_path, "second").unwrap();
+        fs::write(temp_dir.join("second.rs"), "second").unwrap();
+        snap::shot_all(&test_repo).unwrap();
+        reg::run(&test_repo, "second").unwrap();
+
+        // HEAD moved by another process: files and index are put back
+        let transaction = RefTransaction::new(&test_repo, "test").update(
+            refs::HEAD,
+            Expected::Value(first.clone()),
+            &first,
+        );
+        assert!(checkout(&test_repo, &first, LocalChanges::Keep, transaction).is_err());
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "second");
+        assert!(temp_dir.join("second.rs").exists());
+        assert_eq!(test_repo.read_index().unwrap().filemetas.len(), 2);
+        assert_eq!(
+            Head::read(&test_repo).unwrap(),
+            Head::Symbolic(refs::local_path("voyage"))
+        );
+        assert!(status::collect(&test_repo).unwrap().is_clean());
+
+        // Moved together with the files
+        let transaction = RefTransaction::new(&test_repo, "test").update(
+            refs::HEAD,
+            Expected::Value(refs::local_path("voyage")),
+            &first,
+        );
+        checkout(&test_repo, &first, LocalChanges::Keep, transaction).unwrap();
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "first");
+        assert!(!temp_dir.join("second.rs").exists());
+        assert_eq!(Head::read(&test_repo).unwrap(), Head::Detached(first));
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_merge_local() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        let file_path = temp_dir.join("menu.txt");
+        fs::write(&file_path, "cafe\ntea\n").unwrap();
+        snap::shot_all(&test_repo).unwrap();
+        reg::run(&test_repo, "first").unwrap();
+        let first = revision::resolve(&test_repo, "HEAD").unwrap();
+        fs::write(&file_path, "cafe\ntea\ncake\n").unwrap();
+        snap::shot_all(&test_repo).unwrap();
+        reg::run(&test_repo, "second").unwrap();
+
+        // Local change merged into the target
+        fs::write(&file_path, "coffee\ntea\ncake\n").unwrap();
+        run(&test_repo, &first, LocalChanges::Merge).unwrap();
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "coffee\ntea\n");
+
+        // Not UTF-8 local change is not re-encoded
+        run(&test_repo, "voyage", LocalChanges::Discard).unwrap();
+        let local = b"caf\xe9\ntea\ncake\n".to_vec();
+        fs::write(&file_path, &local).unwrap();
+        assert!(run(&test_repo, &first, LocalChanges::Merge).is_err());
+        assert_eq!(fs::read(&file_path).unwrap(), local);
+        assert_eq!(
+            Head::read(&test_repo).unwrap(),
+            Head::Symbolic(refs::local_path("voyage"))
+        );
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
     #[test]
     fn test_delete_file() {}
 }
@@ -3,21 +3,29 @@
 //! Generate objects from actual files and directories.
 
 // Std
+use std::fs;
 use std::io::Write;
+use std::os::unix::fs::MetadataExt;
 use std::path::Path;
 
 // External
 use anyhow::Result;
 
 // Internal
+use crate::util::tree::TreeBuilder;
+use crate::util::{object, pool};
 use nss_core::repository::NssRepository;
 use nss_core::struct_set::{Hashable, Object};
 
 /// Calculate the hash value of the given file and output this.
 #[allow(unused_must_use)]
 pub fn run<P: AsRef<Path>, W: Write>(w: &mut W, target_path: P) -> Result<()> {
-    let object = Object::new(target_path.as_ref())?;
-    writeln!(w, "{}", hex::encode(object.to_hash()));
+    let target_path = target_path.as_ref();
+    let hash = match target_path.is_dir() {
+        true => Object::new(target_path)?.to_hash(),
+        false => object::hash_blob(target_path)?,
+    };
+    writeln!(w, "{}", hex::encode(hash));
 
     Ok(())
 }
@@ -29,15 +37,40 @@ pub fn run_option_w<P: AsRef<Path>, W: Write>(
     target_path: P,
     repository: NssRepository,
 ) -> Result<()> {
-    let object = Object::new(target_path)?;
-    let hash = hex::encode(object.to_hash());
+    let target_path = target_path.as_ref();
+    if target_path.is_dir() {
+        let hash = write_dir(&repository, target_path)?;
+        writeln!(w, "{}", hash);
+
+        return Ok(());
+    }
 
-    repository.write_object(object)?;
-    writeln!(w, "{}", hash);
+    let hash = object::store_blob(&repository, target_path)?;
+    writeln!(w, "{}", hex::encode(hash));
 
     Ok(())
 }
 
+/// Write the blobs under the directory on all cores, then the trees from their hashes.
+fn write_dir(repository: &NssRepository, dir: &Path) -> Result<String> {
+    let mut file_paths = repository.get_all_paths_ignore(dir);
+    file_paths.sort();
+
+    let hashes = pool::parallel_map(&file_paths, |file_path| {
+        object::store_blob(repository, file_path)
+    })
+    .into_iter()
+    .collect::<Result<Vec<Vec<u8>>>>()?;
+
+    let mut files = vec![];
+    for (file_path, hash) in file_paths.iter().zip(hashes) {
+        let mode = fs::metadata(file_path)?.mode();
+        files.push((file_path.strip_prefix(dir)?.to_path_buf(), mode, hash));
+    }
+
+    TreeBuilder::new(repository).write_files(files)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
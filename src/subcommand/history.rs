@@ -1,119 +1,308 @@
+//! **Story command** ... Base command: `git log`
+//!
+//! Walk the commit history from HEAD and display it.
+
 // Std
-use std::fs::File;
-use std::io::prelude::*;
+use std::path::PathBuf;
 
 // External
 use anyhow::{bail, Result};
-use chrono::prelude::{Datelike, Local};
-use chrono::{Month, TimeZone};
 use colored::*;
+use regex::Regex;
 
 // Internal
+use crate::util::date::format_local;
+use crate::util::graph::Graph;
+use crate::util::pretty::{expand, preset, raw, validate};
+use crate::util::refs::{decorations, Decoration};
+use crate::util::revision::{head_hash, resolve_commit};
+use crate::util::tree;
+use crate::util::walk::{CommitNode, Order, RevWalk};
 use nss_core::repository::NssRepository;
-use nss_core::struct_set::{Hashable, Object};
 
-pub fn run(repository: &NssRepository) -> Result<()> {
-    let head_hash = match read_head(repository)? {
-        Some(h) => h,
-        _ => bail!("No history yet. You start new journey!"),
-    };
+/// Which commits to show and how.
+#[derive(Debug, Default)]
+pub struct StoryOption {
+    pub order: Order,
+    pub graph: bool,
+    /// `<rev>` or `<rev>..<rev>` (HEAD if none)
+    pub range: Option<String>,
+    pub max_count: Option<usize>,
+    /// Unix timestamp
+    pub since: Option<i64>,
+    /// Unix timestamp
+    pub until: Option<i64>,
+    pub author: Option<Regex>,
+    pub grep: Option<Regex>,
+    /// Paths from the repository root
+    pub paths: Vec<PathBuf>,
+}
 
-    go_back(repository, &head_hash)?;
+impl StoryOption {
+    fn matches(&self, repository: &NssRepository, node: &CommitNode) -> Result<bool> {
+        let timestamp = node.timestamp();
+        if self.since.map_or(false, |since| timestamp < since)
+            || self.until.map_or(false, |until| timestamp > until)
+        {
+            return Ok(false);
+        }
 
-    Ok(())
+        if let Some(author) = &self.author {
+            if !author.is_match(&node.commit.author) {
+                return Ok(false);
+            }
+        }
+
+        if let Some(grep) = &self.grep {
+            if !grep.is_match(&node.commit.message) {
+                return Ok(false);
+            }
+        }
+
+        if !self.paths.is_empty() && !touches(repository, node, &self.paths)? {
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
 }
 
-pub fn run_option_s(repository: &NssRepository) -> Result<()> {
-    let head_hash = match read_head(repository)? {
-        Some(h) => h,
-        _ => bail!("No history yet. You start new journey!"),
-    };
+type Formatter<'a> = &'a dyn Fn(&CommitNode, Option<&Vec<Decoration>>) -> Vec<String>;
+type BoxedFormatter = Box<dyn Fn(&CommitNode, Option<&Vec<Decoration>>) -> Vec<String>>;
 
-    go_back_option_s(repository, &head_hash)?;
+pub fn run(repository: &NssRepository, option: &StoryOption) -> Result<()> {
+    go_back(repository, option, &format_commit)
+}
 
-    Ok(())
+pub fn run_option_s(repository: &NssRepository, option: &StoryOption) -> Result<()> {
+    go_back(repository, option, &format_commit_short)
 }
 
-#[allow(clippy::format_in_format_args)]
-fn go_back(repository: &NssRepository, hash: &str) -> Result<()> {
-    let object = repository.read_object(hash)?;
+/// Output with the preset (`oneline`, `medium`, `full`, `raw`) or the placeholders.
+pub fn run_option_format(
+    repository: &NssRepository,
+    option: &StoryOption,
+    format: &str,
+) -> Result<()> {
+    let formatter = custom_formatter(format)?;
 
-    let commit = match object {
-        Object::Commit(commit) => commit,
-        _ => bail!("Not commit hash ({})", hex::encode(object.to_hash())),
-    };
+    go_back(repository, option, formatter.as_ref())
+}
 
-    let hash = format!("Commit: {}", hash).yellow();
-    let branch = format!(
-        "({}{})",
-        "HEAD -> ".bright_cyan().bold(),
-        "voyage".bright_green().bold()
-    );
-
-    let author = format!("Author: {}", commit.author);
-    let timestamp = Local.timestamp_opt(commit.date.timestamp(), 0).unwrap();
-    let date = format!(
-        "Date:   {} {:.3} {}",
-        timestamp.weekday(),
-        Month::try_from(timestamp.month() as u8).unwrap().name(),
-        timestamp.format("%d %H:%M:%S %Y %z")
-    );
-    let message = format!("    {}", commit.message);
-
-    // Output
-    println!("{} {}\n{}\n{}\n\n{}\n", hash, branch, author, date, message);
-
-    if commit.parent != *"None" {
-        go_back(repository, &commit.parent)?
+/// Formatter of the preset or the placeholders, checked before any commit is read.
+fn custom_formatter(format: &str) -> Result<BoxedFormatter> {
+    match format {
+        "medium" => return Ok(Box::new(format_commit)),
+        "raw" => {
+            return Ok(Box::new(
+                |node: &CommitNode, _: Option<&Vec<Decoration>>| split_lines(&raw(node)),
+            ))
+        }
+        _ => (),
     }
 
-    Ok(())
+    let template = format.strip_prefix("format:").unwrap_or(format);
+    let template = preset(template).unwrap_or(template).to_string();
+    validate(&template)?;
+
+    Ok(Box::new(
+        move |node: &CommitNode, labels: Option<&Vec<Decoration>>| {
+            split_lines(&expand(&template, node, labels))
+        },
+    ))
 }
 
-#[allow(clippy::format_in_format_args)]
-fn go_back_option_s(repository: &NssRepository, hash: &str) -> Result<()> {
-    let object = repository.read_object(hash)?;
+fn split_lines(text: &str) -> Vec<String> {
+    text.split('\n').map(String::from).collect()
+}
 
-    let commit = match object {
-        Object::Commit(c) => c,
-        _ => todo!(),
+/// Resolve the range into the commits to hide and to start from.
+fn resolve_range(
+    repository: &NssRepository,
+    range: Option<&str>,
+) -> Result<(Option<String>, String)> {
+    let Some(range) = range else {
+        return match head_hash(repository)? {
+            Some(h) => Ok((None, h)),
+            _ => bail!("No history yet. You start new journey!"),
+        };
     };
 
-    println!(
-        "{} {}",
-        format!("{:?}", &hash[0..7]).yellow(),
-        format!("{}", commit.message)
-    );
-    if commit.parent != *"None" {
-        go_back_option_s(repository, &commit.parent)?
+    match range.split_once("..") {
+        Some((_, to)) if to.starts_with('.') => {
+            bail!("Symmetric difference is not supported: {}", range)
+        }
+        Some((from, to)) => {
+            let from = if from.is_empty() { "HEAD" } else { from };
+            let to = if to.is_empty() { "HEAD" } else { to };
+            Ok((
+                Some(resolve_commit(repository, from)?),
+                resolve_commit(repository, to)?,
+            ))
+        }
+        None => Ok((None, resolve_commit(repository, range)?)),
+    }
+}
+
+fn go_back(repository: &NssRepository, option: &StoryOption, formatter: Formatter) -> Result<()> {
+    let (hidden, start) = resolve_range(repository, option.range.as_deref())?;
+    let decorations = decorations(repository)?;
+    let mut graph = option.graph.then(Graph::new);
+
+    let mut walk = RevWalk::new(repository, option.order);
+    if let Some(hidden) = hidden {
+        walk.hide(&hidden)?;
+    }
+    walk.push(&start)?;
+
+    let mut count = 0;
+    while let Some(node) = walk.next() {
+        if option.max_count.map_or(false, |max| count >= max) {
+            break;
+        }
+
+        let node = node?;
+        let parents = node
+            .parents
+            .iter()
+            .filter(|p| !walk.is_hidden(p))
+            .cloned()
+            .collect::<Vec<String>>();
+
+        // Filtered commits only keep the graph lanes connected
+        let lines = match option.matches(repository, &node)? {
+            true => formatter(&node, decorations.get(&node.hash)),
+            false => vec![],
+        };
+        if !lines.is_empty() {
+            count += 1;
+        }
+        print_lines(graph.as_mut(), &node.hash, &parents, &lines);
     }
 
     Ok(())
 }
 
-fn read_head(repository: &NssRepository) -> Result<Option<String>> {
-    let mut file = File::open(repository.head_path()).unwrap();
-    let mut referece = String::new();
-    file.read_to_string(&mut referece).unwrap();
+/// Whether the commit changed any file under the paths from its first parent.
+fn touches(repository: &NssRepository, node: &CommitNode, paths: &[PathBuf]) -> Result<bool> {
+    let parent_tree = match node.parents.first() {
+        Some(parent) => Some(CommitNode::read(repository, parent)?.commit.tree_hash),
+        None => None,
+    };
 
-    let prefix_path = referece.split(' ').collect::<Vec<&str>>();
+    for path in paths {
+        if tree::changed_at(
+            repository,
+            parent_tree.as_deref(),
+            Some(node.commit.tree_hash.as_str()),
+            path,
+        )? {
+            return Ok(true);
+        }
+    }
 
-    if prefix_path[1].contains('/') {
-        let bookmarker = prefix_path[1].split('/').collect::<Vec<&str>>()[2];
+    Ok(false)
+}
 
-        let mut file = File::open(repository.bookmarks_path(bookmarker)).unwrap();
-        let mut hash = String::new();
-        file.read_to_string(&mut hash).unwrap();
+/// Print the lines of the commit, with the graph column on the left if any.
+fn print_lines(graph: Option<&mut Graph>, hash: &str, parents: &[String], lines: &[String]) {
+    match graph {
+        Some(graph) => graph_lines(graph, hash, parents, lines)
+            .iter()
+            .for_each(|line| println!("{}", line)),
+        None => lines.iter().for_each(|line| println!("{}", line)),
+    }
+}
 
-        return Ok(Some(hash));
+/// Lines of the commit prefixed with the graph rows.
+fn graph_lines(graph: &mut Graph, hash: &str, parents: &[String], lines: &[String]) -> Vec<String> {
+    let rows = graph.next(hash, parents);
+    let mut output = rows
+        .before
+        .iter()
+        .map(|row| row.trim_end().to_string())
+        .collect::<Vec<String>>();
+
+    let mut after = rows.after.into_iter();
+    for (i, line) in lines.iter().enumerate() {
+        let prefix = match i {
+            0 => rows.commit.clone(),
+            _ => after.next().unwrap_or_else(|| rows.padding.clone()),
+        };
+        output.push(format!("{}{}", prefix, line).trim_end().to_string());
     }
+    output.extend(after.map(|row| row.trim_end().to_string()));
 
-    Ok(Some(prefix_path[1].to_owned()))
+    output
+}
+
+fn format_decoration(labels: Option<&Vec<Decoration>>) -> String {
+    let labels = match labels {
+        Some(labels) if !labels.is_empty() => labels,
+        _ => return String::new(),
+    };
+
+    let labels = labels
+        .iter()
+        .map(|label| match label {
+            Decoration::Head => "HEAD".bright_cyan().bold().to_string(),
+            Decoration::HeadTo(name) => format!(
+                "{}{}",
+                "HEAD -> ".bright_cyan().bold(),
+                name.bright_green().bold()
+            ),
+            Decoration::Local(name) => name.bright_green().bold().to_string(),
+            Decoration::Remote(name) => name.bright_red().bold().to_string(),
+        })
+        .collect::<Vec<String>>();
+
+    format!(" ({})", labels.join(", "))
+}
+
+fn format_commit(node: &CommitNode, labels: Option<&Vec<Decoration>>) -> Vec<String> {
+    let commit = &node.commit;
+    let mut lines = vec![format!(
+        "{}{}",
+        format!("Commit: {}", node.hash).yellow(),
+        format_decoration(labels)
+    )];
+
+    if node.is_merge() {
+        lines.push(format!(
+            "Merge:  {}",
+            node.parents
+                .iter()
+                .map(|p| &p[0..7])
+                .collect::<Vec<&str>>()
+                .join(" ")
+        ));
+    }
+
+    lines.push(format!("Author: {}", commit.author));
+    lines.push(format!("Date:   {}", format_local(commit.date.timestamp())));
+    lines.push(String::new());
+    lines.push(format!("    {}", commit.message));
+    lines.push(String::new());
+
+    lines
+}
+
+fn format_commit_short(node: &CommitNode, labels: Option<&Vec<Decoration>>) -> Vec<String> {
+    vec![format!(
+        "{}{} {}",
+        format!("{:?}", &node.hash[0..7]).yellow(),
+        format_decoration(labels),
+        node.commit.message
+    )]
 }
 
 #[cfg(test)]
 mod tests {
-    // use super::*;
+    use super::*;
+    use crate::subcommand::{bookmark, go_to, merge, reg, snap, voyage};
+    use crate::util::fixture::write_commit;
+    use std::fs;
+    use testdir::testdir;
 
     #[test]
     fn test_run() {}
@@ -121,6 +310,98 @@ mod tests {
     #[test]
     fn test_run_option_s() {}
 
+    #[test]
+    fn test_run_option_format() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        let root = write_commit(&test_repo, &[], "root");
+        let a1 = write_commit(&test_repo, &[&root], "a1");
+        let b1 = write_commit(&test_repo, &[&root], "b1");
+        let merged = write_commit(&test_repo, &[&a1, &b1], "merge");
+        fs::write(test_repo.bookmarks_path("voyage"), &merged).unwrap();
+        let node = |hash: &str| CommitNode::read(&test_repo, hash).unwrap();
+
+        // Raw has a parent line for each parent
+        let format = custom_formatter("raw").unwrap();
+        let lines = format(&node(&merged), None);
+        assert_eq!(lines[0], format!("commit {}", merged));
+        assert_eq!(lines[2], format!("parent {}", a1));
+        assert_eq!(lines[3], format!("parent {}", b1));
+        assert!(lines[4].starts_with("author "));
+        let lines = format(&node(&a1), None);
+        assert_eq!(lines[2], format!("parent {}", root));
+        assert!(lines[3].starts_with("author "));
+        let lines = format(&node(&root), None);
+        assert!(lines.iter().all(|l| !l.starts_with("parent")));
+
+        // Presets and placeholders
+        let format = custom_formatter("oneline").unwrap();
+        assert_eq!(
+            format(&node(&merged), None),
+            vec![format!("{} merge", &merged[0..7])]
+        );
+        let format = custom_formatter("format:%h%n%P").unwrap();
+        assert_eq!(
+            format(&node(&merged), None),
+            vec![merged[0..7].to_string(), format!("{} {}", a1, b1)]
+        );
+        let format = custom_formatter("medium").unwrap();
+        assert_eq!(
+            format(&node(&merged), None),
+            format_commit(&node(&merged), None)
+        );
+        assert!(custom_formatter("%x").is_err());
+
+        let option = StoryOption::default();
+        assert!(run_option_format(&test_repo, &option, "raw").is_ok());
+        assert!(run_option_format(&test_repo, &option, "format:%H %d").is_ok());
+        assert!(run_option_format(&test_repo, &option, "format:%q").is_err());
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_range() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        // No commit yet
+        assert!(resolve_range(&test_repo, None).is_err());
+
+        let first = write_commit(&test_repo, &[], "first");
+        let second = write_commit(&test_repo, &[&first], "second");
+        fs::write(test_repo.bookmarks_path("voyage"), &second).unwrap();
+
+        assert_eq!(
+            resolve_range(&test_repo, None).unwrap(),
+            (None, second.clone())
+        );
+        assert_eq!(
+            resolve_range(&test_repo, Some("HEAD~")).unwrap(),
+            (None, first.clone())
+        );
+        assert_eq!(
+            resolve_range(&test_repo, Some("HEAD~..voyage")).unwrap(),
+            (Some(first.clone()), second.clone())
+        );
+        assert_eq!(
+            resolve_range(&test_repo, Some("..HEAD~")).unwrap(),
+            (Some(second.clone()), first.clone())
+        );
+        assert!(resolve_range(&test_repo, Some("HEAD~...voyage")).is_err());
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
     #[test]
     fn test_go_back() {}
 
@@ -128,5 +409,114 @@ mod tests {
     fn test_go_back_option_s() {}
 
     #[test]
-    fn test_read_head() {}
+    fn test_touches() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+        let head =
+            || CommitNode::read(&test_repo, &head_hash(&test_repo).unwrap().unwrap()).unwrap();
+        let paths = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<Vec<PathBuf>>();
+
+        fs::create_dir(temp_dir.join("src")).unwrap();
+        fs::write(temp_dir.join("src").join("main.rs"), "fn main() {}").unwrap();
+        fs::write(temp_dir.join("README.md"), "# nssi").unwrap();
+        snap::shot_all(&test_repo).unwrap();
+        reg::run(&test_repo, "root").unwrap();
+        bookmark::run(&test_repo, "topic", None).unwrap();
+
+        // Root commit touches every file it has
+        let root = head();
+        assert!(touches(&test_repo, &root, &paths(&["src"])).unwrap());
+        assert!(!touches(&test_repo, &root, &paths(&["docs"])).unwrap());
+
+        fs::write(temp_dir.join("src").join("main.rs"), "fn main() { run() }").unwrap();
+        snap::shot_all(&test_repo).unwrap();
+        reg::run(&test_repo, "change main").unwrap();
+
+        // Directory and file
+        let changed = head();
+        assert!(touches(&test_repo, &changed, &paths(&["src"])).unwrap());
+        assert!(touches(&test_repo, &changed, &paths(&["src/main.rs"])).unwrap());
+        assert!(!touches(&test_repo, &changed, &paths(&["README.md"])).unwrap());
+        assert!(touches(&test_repo, &changed, &paths(&["README.md", "src"])).unwrap());
+
+        go_to::run(&test_repo, "topic", go_to::LocalChanges::Keep).unwrap();
+        fs::create_dir(temp_dir.join("docs")).unwrap();
+        fs::write(temp_dir.join("docs").join("guide.md"), "# Guide").unwrap();
+        snap::shot_all(&test_repo).unwrap();
+        reg::run(&test_repo, "add guide").unwrap();
+        go_to::run(&test_repo, "voyage", go_to::LocalChanges::Keep).unwrap();
+        merge::run(&test_repo, "topic").unwrap();
+
+        // Merge commit is compared with its first parent
+        let merged = head();
+        assert!(merged.is_merge());
+        assert!(touches(&test_repo, &merged, &paths(&["docs"])).unwrap());
+        assert!(!touches(&test_repo, &merged, &paths(&["src"])).unwrap());
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_format_commit() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        //   root - a1 - merge
+        //       \      /
+        //        b1 --
+        let root = write_commit(&test_repo, &[], "root");
+        let a1 = write_commit(&test_repo, &[&root], "a1");
+        let b1 = write_commit(&test_repo, &[&root], "b1");
+        let merged = write_commit(&test_repo, &[&a1, &b1], "merge");
+
+        let node = CommitNode::read(&test_repo, &merged).unwrap();
+        let labels = vec![Decoration::HeadTo("voyage".to_string())];
+        let lines = format_commit(&node, Some(&labels));
+        assert_eq!(lines.len(), 7);
+        assert!(lines[0].contains(&merged));
+        assert!(lines[0].contains("voyage"));
+        assert_eq!(lines[1], format!("Merge:  {} {}", &a1[0..7], &b1[0..7]));
+        assert_eq!(lines[2], "Author: noshishi\0 ");
+        assert!(lines[3].starts_with("Date:   "));
+        assert_eq!(lines[4], "");
+        assert_eq!(lines[5], "    merge");
+
+        // Single parent has no merge line
+        let node = CommitNode::read(&test_repo, &a1).unwrap();
+        let lines = format_commit(&node, None);
+        assert!(lines[0].contains(&a1));
+        assert!(lines[1].starts_with("Author: "));
+
+        // Graph of the merge history
+        let mut graph = Graph::new();
+        let mut output = vec![];
+        for hash in [&merged, &a1, &b1, &root] {
+            let node = CommitNode::read(&test_repo, hash).unwrap();
+            let lines = vec![node.commit.message.clone()];
+            output.extend(graph_lines(&mut graph, &node.hash, &node.parents, &lines));
+        }
+        assert_eq!(
+            output,
+            vec!["*   merge", "|\\", "* | a1", "| * b1", "|/", "* root"]
+        );
+
+        // Other lines of the commit continue the lanes
+        let mut graph = Graph::new();
+        let node = CommitNode::read(&test_repo, &merged).unwrap();
+        let lines = vec!["merge".to_string(), "body".to_string(), "end".to_string()];
+        assert_eq!(
+            graph_lines(&mut graph, &node.hash, &node.parents, &lines),
+            vec!["*   merge", "|\\  body", "| | end"]
+        );
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
 }
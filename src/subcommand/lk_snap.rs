@@ -4,6 +4,7 @@
 use anyhow::Result;
 
 // Internal
+use crate::util::index::Extensions;
 use nss_core::repository::NssRepository;
 
 pub fn run(repository: &NssRepository) -> Result<()> {
@@ -19,7 +20,13 @@ pub fn run(repository: &NssRepository) -> Result<()> {
 pub fn run_option_s(repository: &NssRepository) -> Result<()> {
     let index = repository.read_index()?;
 
+    let conflicts = Extensions::read(repository)?.conflicts()?;
+
     for filemeta in index.filemetas {
+        // Unmerged path is shown by its stages
+        if conflicts.iter().any(|c| c.path == filemeta.filename) {
+            continue;
+        }
         println!(
             "{:0>6o} {} 0\t{}",
             filemeta.mode,
@@ -28,6 +35,15 @@ pub fn run_option_s(repository: &NssRepository) -> Result<()> {
         );
     }
 
+    for conflict in conflicts {
+        println!(
+            "{} {}\t{}",
+            conflict.hash,
+            conflict.stage,
+            conflict.path.display()
+        );
+    }
+
     Ok(())
 }
 
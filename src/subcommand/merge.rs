@@ -0,0 +1,493 @@
+//! **Merge command** ... Base command: `git merge`
+//!
+//! Join the history of another bookmark into the current one.
+//! When the current commit is an ancestor of the other, HEAD just
+//! moves forward (fast-forward). Otherwise each changed file is merged
+//! line by line from the merge base, and the result is registered as
+//! a commit having two parents.
+//!
+//! When some files conflict, the merge stops with conflict markers in
+//! the working directory, and their stages are recorded with the index.
+//! Fix them, `snap` them and `reg` to finish.
+//!
+//! **Note:** The commit object keeps parents in one field, so they are
+//! encoded through [`Parents`](crate::util::parents::Parents).
+
+// Std
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// External
+use anyhow::{bail, Context, Result};
+use colored::*;
+
+// Internal
+use super::{go_to, reg, status};
+use crate::util::head::Head;
+use crate::util::index::{
+    write_index_with, Conflict, Extensions, STAGE_BASE, STAGE_OURS, STAGE_THEIRS,
+};
+use crate::util::object;
+use crate::util::refs::{Expected, RefTransaction};
+use crate::util::revision::{self, head_hash};
+use crate::util::three_way;
+use crate::util::tree;
+use crate::util::walk::merge_base;
+use crate::util::worktree::{self, Content, Update};
+use nss_core::repository::NssRepository;
+use nss_core::struct_set::Index;
+
+pub fn run(repository: &NssRepository, bookmarker: &str) -> Result<()> {
+    if merge_head(repository)?.is_some() {
+        bail!("You are in the middle of a merge. Resolve conflicts, snap and reg first.");
+    }
+
+    let ours = match head_hash(repository)? {
+        Some(h) => h,
+        None => bail!("No history yet. You start new journey!"),
+    };
+    let theirs = revision::resolve_commit(repository, bookmarker)?;
+
+    let status = status::collect(repository)?;
+    if !(status.staged.is_empty() && status.modified.is_empty() && status.deleted.is_empty()) {
+        bail!("Your local changes would be overwritten by merge. Please reg them first.");
+    }
+
+    let base = match merge_base(repository, &ours, &theirs)? {
+        Some(base) => base,
+        None => bail!("{} has no common history with HEAD", bookmarker),
+    };
+
+    if base == theirs {
+        println!("Already up to date.");
+        return Ok(());
+    }
+
+    if base == ours {
+        println!(
+            "Fast-forward {} --> {}",
+            &ours[0..7].bright_blue(),
+            &theirs[0..7].bright_yellow()
+        );
+        let reason = format!("merge {}: Fast-forward", bookmarker);
+        let transaction = move_head(repository, &ours, &theirs, &reason)?;
+        return go_to::checkout(repository, &theirs, go_to::LocalChanges::Keep, transaction);
+    }
+
+    let conflicts = merge_trees(
+        repository,
+        &base,
+        &ours,
+        &theirs,
+        bookmarker,
+        &status.untracked,
+    )?;
+
+    if conflicts.is_empty() {
+        return reg::run(repository, &format!("Merge bookmark '{}'", bookmarker));
+    }
+
+    let paths = conflicts
+        .iter()
+        .map(|c| &c.path)
+        .collect::<BTreeSet<&PathBuf>>();
+    for path in paths {
+        println!("{}: {}", "CONFLICT".red().bold(), path.display());
+    }
+    println!("Automatic merge failed. Fix conflicts, snap them and reg the result.");
+
+    Ok(())
+}
+
+/// Merge every file, update the working directory and index
+/// (with the stages of unmerged paths) and MERGE_HEAD, and return the conflicts.
+///
+/// **Note:** Files are replaced through [`worktree::apply`], so a failure on
+/// the way puts them back and leaves no merge state.
+fn merge_trees(
+    repository: &NssRepository,
+    base: &str,
+    ours: &str,
+    theirs: &str,
+    bookmarker: &str,
+    untracked: &[PathBuf],
+) -> Result<Vec<Conflict>> {
+    let base_files = hash_map(tree::to_index(repository, base)?);
+    let ours_files = hash_map(tree::to_index(repository, ours)?);
+    let theirs_files = hash_map(tree::to_index(repository, theirs)?);
+
+    let untracked = untracked
+        .iter()
+        .filter(|p| theirs_files.contains_key(*p))
+        .collect::<Vec<_>>();
+    if !untracked.is_empty() {
+        bail!(
+            "The following untracked files would be overwritten by merge:\n{}Please move or remove them before you merge.",
+            untracked
+                .iter()
+                .map(|p| format!("\t{}\n", p.display()))
+                .collect::<String>()
+        )
+    }
+
+    let paths = base_files
+        .keys()
+        .chain(ours_files.keys())
+        .chain(theirs_files.keys())
+        .cloned()
+        .collect::<BTreeSet<PathBuf>>();
+
+    let mut updates = vec![];
+    // Paths registered from the merged files, and whether the blob is new
+    let mut merged = vec![];
+    // Index keeps our version of the unmerged path until it is resolved
+    let mut index = Index::empty();
+    let mut conflicts = vec![];
+    for path in paths {
+        let b = base_files.get(&path);
+        let o = ours_files.get(&path);
+        let t = theirs_files.get(&path);
+        let full_path = repository.path().join(&path);
+
+        // Resolved without looking into the content
+        let trivial = if o == t || t == b {
+            Some(o)
+        } else if o == b {
+            Some(t)
+        } else {
+            None
+        };
+
+        match trivial {
+            Some(Some(hash)) => {
+                if Some(hash) != o {
+                    updates.push(Update {
+                        path: path.clone(),
+                        content: Some(Content::Blob(hash.clone())),
+                    });
+                }
+                merged.push((path, false));
+            }
+            Some(None) => {
+                if o.is_some() {
+                    updates.push(Update {
+                        path,
+                        content: None,
+                    });
+                }
+            }
+            None => {
+                let (Some(o_hash), Some(t_hash)) = (o, t) else {
+                    // Modified on one side and deleted on the other
+                    match t {
+                        Some(t_hash) => updates.push(Update {
+                            path: path.clone(),
+                            content: Some(Content::Blob(t_hash.clone())),
+                        }),
+                        None => index.add(repository, &full_path, None)?,
+                    }
+                    conflicts.extend(stages(&path, b, o, t));
+                    continue;
+                };
+
+                let base_content = match b {
+                    Some(hash) => object::read_blob(repository, hash)?,
+                    None => vec![],
+                };
+                let ours_content = object::read_blob(repository, o_hash)?;
+                let theirs_content = object::read_blob(repository, t_hash)?;
+
+                let (Some(base_text), Some(ours_text), Some(theirs_text)) = (
+                    three_way::as_text(&base_content),
+                    three_way::as_text(&ours_content),
+                    three_way::as_text(&theirs_content),
+                ) else {
+                    // Keep our version for binary (or not UTF-8) file
+                    index.add(repository, &full_path, None)?;
+                    conflicts.extend(stages(&path, b, o, t));
+                    continue;
+                };
+
+                let result =
+                    three_way::merge3(base_text, ours_text, theirs_text, "HEAD", bookmarker);
+
+                if result.conflicts == 0 {
+                    merged.push((path.clone(), true));
+                } else {
+                    index.add(repository, &full_path, None)?;
+                    conflicts.extend(stages(&path, b, o, t));
+                }
+                updates.push(Update {
+                    path,
+                    content: Some(Content::Data(result.text.into_bytes())),
+                });
+            }
+        }
+    }
+
+    // Nothing is left half merged if it fails on the way
+    let merge_head_path = repository.path().join(".nss").join("MERGE_HEAD");
+    worktree::apply(repository, &updates, || {
+        for (path, store) in &merged {
+            let full_path = repository.path().join(path);
+            if *store {
+                object::store_blob(repository, &full_path)?;
+            }
+            index.add(repository, &full_path, None)?;
+        }
+        index.filemetas.sort_by(|a, b| a.filename.cmp(&b.filename));
+
+        // Keep the state for reg
+        fs::write(&merge_head_path, theirs)?;
+        let mut extensions = Extensions::default();
+        extensions.set_conflicts(&conflicts);
+        let result = write_index_with(repository, index, &extensions);
+        if result.is_err() {
+            let _ = fs::remove_file(&merge_head_path);
+        }
+        result
+    })
+    .with_context(|| format!("Can't merge {}", bookmarker))?;
+
+    Ok(conflicts)
+}
+
+fn stages(
+    path: &Path,
+    base: Option<&String>,
+    ours: Option<&String>,
+    theirs: Option<&String>,
+) -> Vec<Conflict> {
+    [
+        (STAGE_BASE, base),
+        (STAGE_OURS, ours),
+        (STAGE_THEIRS, theirs),
+    ]
+    .into_iter()
+    .filter_map(|(stage, hash)| {
+        hash.map(|h| Conflict {
+            stage,
+            hash: h.to_string(),
+            path: path.to_path_buf(),
+        })
+    })
+    .collect()
+}
+
+fn hash_map(index: Index) -> HashMap<PathBuf, String> {
+    index
+        .filemetas
+        .into_iter()
+        .map(|f| (f.filename, hex::encode(f.hash)))
+        .collect()
+}
+
+/// Transaction moving the bookmark pointed by HEAD (or HEAD itself when
+/// detached) unless it was moved from our commit meanwhile.
+fn move_head<'a>(
+    repository: &'a NssRepository,
+    ours: &str,
+    theirs: &str,
+    reason: &str,
+) -> Result<RefTransaction<'a>> {
+    let head = Head::read(repository)?;
+
+    Ok(RefTransaction::new(repository, reason).update(
+        head.target(),
+        Expected::Value(ours.to_string()),
+        theirs,
+    ))
+}
+
+/// Commit hash being merged, `None` if no merge is in progress.
+pub fn merge_head(repository: &NssRepository) -> Result<Option<String>> {
+    let path = repository.path().join(".nss").join("MERGE_HEAD");
+    match fs::read_to_string(path) {
+        Ok(hash) => Ok(Some(hash.trim().to_string())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).context("Can't read MERGE_HEAD"),
+    }
+}
+
+/// Remove the merge state after the merge commit is registered.
+pub fn clear_state(repository: &NssRepository) -> Result<()> {
+    let path = repository.path().join(".nss").join("MERGE_HEAD");
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::{bookmark, snap, voyage};
+    use testdir::testdir;
+
+    #[test]
+    fn test_run_conflict() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        let file_path = temp_dir.join("first.rs");
+        fs::write(&file_path, "fn main() {}\n").unwrap();
+        snap::shot_all(&test_repo).unwrap();
+        reg::run(&test_repo, "base").unwrap();
+        bookmark::run(&test_repo, "topic", None).unwrap();
+
+        fs::write(&file_path, "fn main() { ours() }\n").unwrap();
+        snap::shot_all(&test_repo).unwrap();
+        reg::run(&test_repo, "ours").unwrap();
+
+        go_to::run(&test_repo, "topic", go_to::LocalChanges::Keep).unwrap();
+        fs::write(&file_path, "fn main() { theirs() }\n").unwrap();
+        snap::shot_all(&test_repo).unwrap();
+        reg::run(&test_repo, "theirs").unwrap();
+
+        run(&test_repo, "voyage").unwrap();
+
+        // Stages are recorded with the index
+        let conflicts = Extensions::read(&test_repo).unwrap().conflicts().unwrap();
+        assert_eq!(
+            conflicts.iter().map(|c| c.stage).collect::<Vec<u8>>(),
+            vec![STAGE_BASE, STAGE_OURS, STAGE_THEIRS]
+        );
+        assert!(conflicts.iter().all(|c| c.path == Path::new("first.rs")));
+
+        let status = status::collect(&test_repo).unwrap();
+        assert_eq!(status.unmerged, vec![PathBuf::from("first.rs")]);
+        assert!(status.modified.is_empty());
+        assert!(!status.is_clean());
+
+        // Unmerged path blocks reg
+        assert!(reg::run(&test_repo, "merge").is_err());
+
+        // Snapping the resolved file clears its stages
+        fs::write(&file_path, "fn main() { ours(); theirs() }\n").unwrap();
+        snap::shot(&test_repo, file_path.to_str().unwrap()).unwrap();
+        assert!(Extensions::read(&test_repo)
+            .unwrap()
+            .conflicts()
+            .unwrap()
+            .is_empty());
+        assert!(status::collect(&test_repo).unwrap().unmerged.is_empty());
+
+        reg::run(&test_repo, "merge").unwrap();
+        assert_eq!(merge_head(&test_repo).unwrap(), None);
+        assert!(status::collect(&test_repo).unwrap().is_clean());
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_not_utf8() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        // Latin-1 text changed on different lines
+        let file_path = temp_dir.join("menu.txt");
+        fs::write(&file_path, b"caf\xe9\nth\xe9\n").unwrap();
+        snap::shot_all(&test_repo).unwrap();
+        reg::run(&test_repo, "base").unwrap();
+        bookmark::run(&test_repo, "topic", None).unwrap();
+
+        let ours = b"caf\xe9 cr\xe8me\nth\xe9\n".to_vec();
+        fs::write(&file_path, &ours).unwrap();
+        snap::shot_all(&test_repo).unwrap();
+        reg::run(&test_repo, "ours").unwrap();
+
+        go_to::run(&test_repo, "topic", go_to::LocalChanges::Keep).unwrap();
+        fs::write(&file_path, b"caf\xe9\nth\xe9 glac\xe9\n").unwrap();
+        snap::shot_all(&test_repo).unwrap();
+        reg::run(&test_repo, "theirs").unwrap();
+        go_to::run(&test_repo, "voyage", go_to::LocalChanges::Keep).unwrap();
+
+        // Not re-encoded: a conflict keeping our version
+        run(&test_repo, "topic").unwrap();
+        assert_eq!(fs::read(&file_path).unwrap(), ours);
+        assert_eq!(
+            status::collect(&test_repo).unwrap().unmerged,
+            vec![PathBuf::from("menu.txt")]
+        );
+        assert!(merge_head(&test_repo).unwrap().is_some());
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_worktree() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        let file_path = temp_dir.join("first.rs");
+        let guide_path = temp_dir.join("docs").join("guide.md");
+        fs::write(&file_path, "fn main() {}\n").unwrap();
+        fs::create_dir(temp_dir.join("docs")).unwrap();
+        fs::write(&guide_path, "# Guide\n").unwrap();
+        snap::shot_all(&test_repo).unwrap();
+        reg::run(&test_repo, "base").unwrap();
+        bookmark::run(&test_repo, "topic", None).unwrap();
+
+        fs::write(&file_path, "fn main() { ours() }\n").unwrap();
+        snap::shot_all(&test_repo).unwrap();
+        reg::run(&test_repo, "ours").unwrap();
+
+        go_to::run(&test_repo, "topic", go_to::LocalChanges::Keep).unwrap();
+        fs::write(temp_dir.join("second.rs"), "fn second() {}\n").unwrap();
+        fs::remove_dir_all(temp_dir.join("docs")).unwrap();
+        snap::shot_all(&test_repo).unwrap();
+        reg::run(&test_repo, "add second and remove guide").unwrap();
+        go_to::run(&test_repo, "voyage", go_to::LocalChanges::Keep).unwrap();
+
+        // Untracked file added on their side is not overwritten
+        fs::write(temp_dir.join("second.rs"), "untracked").unwrap();
+        assert!(run(&test_repo, "topic").is_err());
+        assert_eq!(
+            fs::read_to_string(temp_dir.join("second.rs")).unwrap(),
+            "untracked"
+        );
+        assert_eq!(merge_head(&test_repo).unwrap(), None);
+
+        // Broken object on their side leaves nothing half merged
+        fs::remove_file(temp_dir.join("second.rs")).unwrap();
+        let theirs = revision::resolve(&test_repo, "topic").unwrap();
+        let blob = tree::to_index(&test_repo, &theirs).unwrap().filemetas[1]
+            .hash
+            .clone();
+        let blob_path = test_repo.objects_path(&hex::encode(&blob));
+        let saved = fs::read(&blob_path).unwrap();
+        fs::remove_file(&blob_path).unwrap();
+        assert!(run(&test_repo, "topic").is_err());
+        assert!(!temp_dir.join("second.rs").exists());
+        assert!(guide_path.exists());
+        assert_eq!(merge_head(&test_repo).unwrap(), None);
+        assert!(status::collect(&test_repo).unwrap().is_clean());
+        fs::write(&blob_path, saved).unwrap();
+
+        run(&test_repo, "topic").unwrap();
+        assert!(head_hash(&test_repo).unwrap().is_some());
+        assert_eq!(
+            fs::read_to_string(temp_dir.join("second.rs")).unwrap(),
+            "fn second() {}\n"
+        );
+        // Directory left empty is removed
+        assert!(!temp_dir.join("docs").exists());
+        assert!(status::collect(&test_repo).unwrap().is_clean());
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+}
@@ -10,13 +10,14 @@ use std::io::Write;
 use anyhow::Result;
 
 // Internal
+use crate::util::revision;
 use nss_core::repository::NssRepository;
 
 /// Register the object into object database (repository)
 /// and Display on standart-output.
 #[allow(unused_must_use)]
 pub fn run_option_p<W: Write>(w: &mut W, repository: &NssRepository, hash: &str) -> Result<()> {
-    let object = repository.read_object(hash)?;
+    let object = repository.read_object(revision::resolve(repository, hash)?)?;
     writeln!(w, "{}", object);
 
     Ok(())
@@ -25,7 +26,7 @@ pub fn run_option_p<W: Write>(w: &mut W, repository: &NssRepository, hash: &str)
 /// Output the object type
 #[allow(unused_must_use)]
 pub fn run_option_t<W: Write>(w: &mut W, repository: &NssRepository, hash: &str) -> Result<()> {
-    let object = repository.read_object(hash)?;
+    let object = repository.read_object(revision::resolve(repository, hash)?)?;
     writeln!(w, "{}", object.as_str());
 
     Ok(())
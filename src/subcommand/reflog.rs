@@ -0,0 +1,96 @@
+//! **Reflog command** ... Base command: `git reflog`
+//!
+//! Show the previous values of HEAD or the bookmark, newest first.
+//! Each value can be referred as `<ref>@{<n>}`.
+
+// External
+use anyhow::Result;
+use colored::*;
+
+// Internal
+use crate::util::pretty::ABBREV_LEN;
+use crate::util::{reflog, refs};
+use nss_core::repository::NssRepository;
+
+pub fn run(repository: &NssRepository, name: Option<&String>) -> Result<()> {
+    let name = name.map(|n| n.as_str()).unwrap_or(refs::HEAD);
+
+    for line in format_entries(repository, name)? {
+        println!("{}", line);
+    }
+
+    Ok(())
+}
+
+fn format_entries(repository: &NssRepository, name: &str) -> Result<Vec<String>> {
+    let entries = reflog::read(repository, &refs::reference_of(name))?;
+
+    Ok(entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            format!(
+                "{} {}@{{{}}}: {}",
+                &entry.new[0..ABBREV_LEN.min(entry.new.len())].yellow(),
+                name,
+                i,
+                entry.reason
+            )
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::voyage;
+    use crate::util::fixture::write_commit;
+    use crate::util::refs::{Expected, RefTransaction};
+    use std::fs;
+    use testdir::testdir;
+
+    #[test]
+    fn test_run() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        // No entry yet
+        assert!(format_entries(&test_repo, "HEAD").unwrap().is_empty());
+        assert!(run(&test_repo, None).is_ok());
+
+        let first = write_commit(&test_repo, &[], "first");
+        let second = write_commit(&test_repo, &[&first], "second");
+        let voyage = refs::local_path("voyage");
+        RefTransaction::new(&test_repo, "reg (initial): first")
+            .update(&voyage, Expected::Empty, &first)
+            .commit()
+            .unwrap();
+        RefTransaction::new(&test_repo, "reg: second")
+            .update(&voyage, Expected::Value(first.clone()), &second)
+            .commit()
+            .unwrap();
+
+        // Failed update is not logged
+        assert!(RefTransaction::new(&test_repo, "reg: lost")
+            .update(&voyage, Expected::Value(first.clone()), &first)
+            .commit()
+            .is_err());
+
+        // Newest first, in the reflog of both HEAD and the bookmark
+        for name in ["HEAD", "voyage"] {
+            let lines = format_entries(&test_repo, name).unwrap();
+            assert_eq!(lines.len(), 2);
+            assert!(lines[0].contains(&second[0..7]));
+            assert!(lines[0].ends_with(&format!("{}@{{0}}: reg: second", name)));
+            assert!(lines[1].contains(&first[0..7]));
+            assert!(lines[1].ends_with(&format!("{}@{{1}}: reg (initial): first", name)));
+        }
+        assert!(run(&test_repo, Some(&"voyage".to_string())).is_ok());
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+}
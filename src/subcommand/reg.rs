@@ -1,36 +1,47 @@
 //! **Reg command** ... Base command: `git commit` and `git commit-tree`
 
-// Std
-use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
-use std::io::prelude::*;
-use std::path::PathBuf;
-
 // External
 use anyhow::{bail, Result};
 use colored::*;
 
 // Internal
-use nss_core::nss_io::file_system;
+use super::merge;
+use crate::util::cache_tree::CachedTree;
+use crate::util::head::Head;
+use crate::util::index::Extensions;
+use crate::util::parents::Parents;
+use crate::util::refs::{Expected, RefTransaction};
+use crate::util::revision::head_hash;
+use crate::util::tree::TreeBuilder;
 use nss_core::repository::NssRepository;
-use nss_core::struct_set::{Commit, Entry, Hashable, Index, Object, Tree};
+use nss_core::struct_set::{Commit, Hashable};
 
 pub fn run(repository: &NssRepository, massage: &str) -> Result<()> {
     // Create tree object from index
     let hash = write_tree(repository)?;
 
-    // Read head hash
-    let head_hash = match head_hash(repository)? {
-        Some(h) => h,
-        _ => "None".to_owned(),
-    };
+    // Read head hash, no parent for the first commit
+    let head_hash = head_hash(repository)?;
+    let mut parents = head_hash.iter().cloned().collect::<Vec<String>>();
+
+    // Merged commit becomes the second parent
+    if let Some(merge_head) = merge::merge_head(repository)? {
+        let conflicts = Extensions::read(repository)?.conflicts()?;
+        if let Some(conflict) = conflicts.first() {
+            bail!(
+                "{} is not resolved yet. Fix the conflict and snap it.",
+                conflict.path.display()
+            );
+        }
+        parents.push(merge_head);
+    }
 
     let config = repository.read_config()?;
 
     // Build commit object
     let commit = Commit::new(
         hash,
-        head_hash,
+        Parents::new(parents).encode(),
         format!(
             "{}\0 {}",
             config.username(),
@@ -48,27 +59,31 @@ pub fn run(repository: &NssRepository, massage: &str) -> Result<()> {
     let hash = hex::encode(commit.to_hash());
     repository.write_object(commit.clone())?;
 
-    display_result(repository, commit.parent.as_str(), hash.as_str())?;
+    display_result(repository, head_hash.as_deref(), hash.as_str(), massage)?;
+    merge::clear_state(repository)?;
 
     Ok(())
 }
 
-fn display_result(repository: &NssRepository, old_hash: &str, new_hash: &str) -> Result<()> {
+fn display_result(
+    repository: &NssRepository,
+    old_hash: Option<&str>,
+    new_hash: &str,
+    massage: &str,
+) -> Result<()> {
     match old_hash {
-        "None" => {
+        None => {
             println!(
-                "{}: {} --> {}: {}",
+                "{}: None --> {}: {}",
                 "OLD".bright_blue(),
-                &old_hash,
                 "NEW".bright_yellow(),
                 &new_hash[0..7]
             );
 
-            let book_path = read_head(repository)?;
-            let bookmarker = book_path.split('/').collect::<Vec<&str>>()[2];
-            update_bookmark(repository, bookmarker, new_hash, None)?;
+            let reason = format!("reg (initial): {}", massage);
+            update_head(repository, new_hash, None, &reason)?;
         }
-        _ => {
+        Some(old_hash) => {
             println!(
                 "{}: {} --> {}: {}",
                 "OLD".bright_blue(),
@@ -77,184 +92,66 @@ fn display_result(repository: &NssRepository, old_hash: &str, new_hash: &str) ->
                 &new_hash[0..7]
             );
 
-            let book_path = read_head(repository)?;
-            let bookmarker = book_path.split('/').collect::<Vec<&str>>()[2];
-            update_bookmark(repository, bookmarker, new_hash, Some(old_hash))?;
+            let reason = format!("reg: {}", massage);
+            update_head(repository, new_hash, Some(old_hash), &reason)?;
         }
     }
 
     Ok(())
 }
 
-fn read_head(repository: &NssRepository) -> Result<String> {
-    let mut file = File::open(repository.head_path())?;
-    let mut referece = String::new();
-    file.read_to_string(&mut referece).unwrap();
-
-    let prefix_path = referece.split(' ').collect::<Vec<&str>>();
-
-    Ok(prefix_path[1].to_string())
-}
-
-fn head_hash(repository: &NssRepository) -> Result<Option<String>> {
-    let head_item = read_head(repository)?;
-    if head_item.contains('/') {
-        let bookmarker = head_item.split('/').collect::<Vec<&str>>()[2];
-
-        let mut file = File::open(repository.bookmarks_path(bookmarker)).unwrap();
-        let mut hash = String::new();
-        file.read_to_string(&mut hash).unwrap();
-
-        if hash == *"" {
-            return Ok(None);
-        }
-
-        return Ok(Some(hash));
-    }
-
-    Ok(Some(head_item))
-}
-
-fn update_bookmark(
+/// Move the bookmark being on, or HEAD itself when detached.
+fn update_head(
     repository: &NssRepository,
-    bookmarker: &str,
     new_commit: &str,
     old_commit: Option<&str>,
+    reason: &str,
 ) -> Result<()> {
-    let object = repository.read_object(new_commit)?;
-    if object.as_str() == "commit" {
-        let mut file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .truncate(true)
-            .open(repository.bookmarks_path(bookmarker))?;
-        let mut bookmark_hash = String::new();
-        file.read_to_string(&mut bookmark_hash)?;
-
-        if bookmark_hash.is_empty() {
-            file.write_all(new_commit.as_bytes())?;
-        } else if let Some(commit) = old_commit {
-            if bookmark_hash == commit {
-                file.write_all(new_commit.as_bytes())?;
-            } else {
-                bail!(
-                    "This bookmarker has the difference old hash ({})",
-                    bookmark_hash
-                );
-            }
-        } else {
-            bail!(
-                "This bookmarker has the difference old hash ({})",
-                bookmark_hash
-            );
-        }
-    } else {
-        bail!("Not commit hash <new commit> ({})", new_commit)
+    let head = Head::read(repository)?;
+    if let Head::Detached(_) = head {
+        println!(
+            "{}: HEAD is detached, so no bookmark points to this commit.\n\
+             Leave a bookmark not to lose it: nssi bookmark <bookmarker> {}",
+            "Warning".yellow().bold(),
+            &new_commit[0..7]
+        );
     }
 
-    Ok(())
+    // Another process may have moved it since HEAD was read
+    RefTransaction::new(repository, reason)
+        .update(
+            head.target(),
+            Expected::from(old_commit.map(String::from)),
+            new_commit,
+        )
+        .commit()
 }
 
 fn write_tree(repository: &NssRepository) -> Result<String> {
-    let index = repository.read_index()?;
-    let tree_dir = tree_map(index)?;
-
-    let mut repo_tree_hash = String::new();
-    let mut dir_entry_map: HashMap<PathBuf, Entry> = HashMap::new();
-    for m in tree_dir {
-        let mut entries: Vec<Entry> = vec![];
-
-        for path in m.1 {
-            if path.is_file() {
-                let object = Object::new(&path)?;
-                let entry = Entry::new(path, object)?;
-                entries.push(entry)
-            } else {
-                let entry = dir_entry_map.get(&path).unwrap().to_owned();
-                entries.push(entry)
-            }
-        }
+    // Cached tree is read before the index it belongs to
+    let extensions = Extensions::read(repository)?;
+    let mut cache = CachedTree::from_extensions(&extensions)?;
 
-        let dir_entry = Entry::new_group(&m.0, entries.clone())?;
-        dir_entry_map.insert(m.0.to_path_buf(), dir_entry);
-
-        let tree = Tree::from_entries(entries);
-        let hash = hex::encode(tree.to_hash());
-        repository.write_object(tree)?;
-
-        if m.0 == repository.path() {
-            repo_tree_hash = hash
-        }
-    }
-
-    Ok(repo_tree_hash)
-}
-
-fn tree_map(index: Index) -> Result<Vec<(PathBuf, Vec<PathBuf>)>> {
-    let mut file_paths: Vec<PathBuf> = vec![];
-    let mut dir_paths: Vec<PathBuf> = vec![];
-    for filemeta in index.filemetas {
-        let repo_path = file_system::exists_repo::<PathBuf>(None)?;
-        let file_path = repo_path.join(filemeta.filename);
-        let mut dir_name = file_path.parent().unwrap().to_path_buf();
-
-        file_paths.push(repo_path.join(file_path));
-        dir_paths.push(repo_path.clone());
-
-        while dir_name != repo_path {
-            dir_paths.push(repo_path.join(dir_name.clone()));
-
-            dir_name = dir_name.parent().unwrap().to_path_buf();
-        }
-    }
-    dir_paths.sort();
-    dir_paths.dedup();
-
-    let mut temp_map: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
-    for dir in &dir_paths {
-        temp_map.insert(dir.to_path_buf(), vec![]);
-
-        for file in &file_paths {
-            if dir == &file.parent().unwrap().to_path_buf() {
-                temp_map
-                    .get_mut(&dir.to_path_buf())
-                    .unwrap()
-                    .push(file.to_path_buf())
-            }
-        }
-
-        for sub_dir in &dir_paths {
-            if dir == &sub_dir.parent().unwrap().to_path_buf() {
-                temp_map
-                    .get_mut(&dir.to_path_buf())
-                    .unwrap()
-                    .push(sub_dir.to_path_buf())
-            }
-        }
-    }
-
-    let mut tmp: Vec<(&PathBuf, &Vec<PathBuf>)> = temp_map.iter().collect();
-    tmp.sort_by(|b, a| {
-        let comp_a: Vec<&std::ffi::OsStr> = a.0.iter().collect();
-        let comp_b: Vec<&std::ffi::OsStr> = b.0.iter().collect();
-        if comp_a.len() >= comp_b.len() {
-            std::cmp::Ordering::Greater
-        } else {
-            std::cmp::Ordering::Less
-        }
-    });
+    let index = repository.read_index()?;
+    let head_hash = head_hash(repository)?;
 
-    let mut tree_dir: Vec<(PathBuf, Vec<PathBuf>)> = vec![];
-    for t in tmp {
-        tree_dir.push((t.0.to_owned(), t.1.to_vec()))
-    }
+    let hash = TreeBuilder::new(repository)
+        .base(head_hash.as_deref())?
+        .cache(&mut cache)
+        .write(&index)?;
+    cache.write(repository, extensions)?;
 
-    Ok(tree_dir)
+    Ok(hash)
 }
 
 #[cfg(test)]
 mod tests {
-    // use super::*;
+    use super::*;
+    use crate::subcommand::{snap, voyage};
+    use nss_core::struct_set::Object;
+    use std::fs;
+    use std::path::Path;
+    use testdir::testdir;
 
     #[test]
     fn test_run() {
@@ -264,14 +161,40 @@ mod tests {
     }
 
     #[test]
-    fn test_read_head() {}
-
-    #[test]
-    fn test_head_hash() {}
-
-    #[test]
-    fn test_write_tree() {}
+    fn test_write_tree() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        fs::write(temp_dir.join("first.rs"), "first").unwrap();
+        snap::shot_all(&test_repo).unwrap();
+        let first_tree = write_tree(&test_repo).unwrap();
+        let cache = CachedTree::read(&test_repo).unwrap();
+        assert_eq!(cache.get(Path::new("")), Some((1, first_tree.as_str())));
+
+        // Index updated by another tool, so the cached tree is stale
+        fs::write(temp_dir.join("second.rs"), "second").unwrap();
+        let mut index = test_repo.read_index().unwrap();
+        index
+            .add(&test_repo, &temp_dir.join("second.rs"), None)
+            .unwrap();
+        test_repo.write_index(index.clone()).unwrap();
+
+        let tree = TreeBuilder::new(&test_repo).write(&index).unwrap();
+        assert_ne!(tree, first_tree);
+
+        // Registered commit has the tree of the current index
+        run(&test_repo, "second").unwrap();
+        let head = head_hash(&test_repo).unwrap().unwrap();
+        match test_repo.read_object(&head).unwrap() {
+            Object::Commit(commit) => assert_eq!(commit.tree_hash, tree),
+            _ => panic!("Not commit hash ({})", head),
+        }
+        assert_eq!(write_tree(&test_repo).unwrap(), tree);
 
-    #[test]
-    fn test_tree_map() {}
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
 }
@@ -1,37 +1,36 @@
 //! **Snap command** Base command: `git add`
 
+// Std
+use std::fs;
+
 // External
-use anyhow::Result;
+use anyhow::{Context, Result};
 
 // Internel
 use super::up_snap;
+use crate::util::{object, stat};
 use nss_core::repository::NssRepository;
-use nss_core::struct_set::Blob;
 
 pub fn shot(repository: &NssRepository, file_path: &str) -> Result<()> {
-    let blob = Blob::new(file_path)?;
-    match repository.write_object(blob) {
-        Ok(()) => (),
-        Err(_e) => ()
-    };
+    let path = repository.path().join(file_path);
+
+    // Existing object is not written again, any other failure aborts the snap
+    let metadata = fs::metadata(&path).with_context(|| format!("Can't snap {}", file_path))?;
+    let hash = object::store_blob(repository, &path)
+        .with_context(|| format!("Can't snap {}", file_path))?;
 
-    up_snap::run(repository, file_path)?;
+    // Index entry is built from the hash of the stored blob
+    up_snap::add_entry(
+        repository,
+        stat::filemeta(repository, &path, &metadata, hash)?,
+    )?;
 
     Ok(())
 }
 
 pub fn shot_all(repository: &NssRepository) -> Result<()> {
-    let all_files = repository.get_all_paths_ignore(repository.path());
-
-    for file_path in all_files {
-        let blob = Blob::new(file_path)?;
-        match repository.write_object(blob) {
-            Ok(()) => (),
-            Err(_e) => ()
-        };
-    }
-
-    up_snap::run_all(repository)?;
+    // Only files changed since the last snap are hashed and written
+    up_snap::update_all(repository, true)?;
 
     Ok(())
 }
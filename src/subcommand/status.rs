@@ -0,0 +1,209 @@
+//! **Status command** ... Base command: `git status`
+//!
+//! Compare the HEAD commit, the index and the working directory,
+//! and show which files are staged, modified, deleted, untracked or
+//! left unmerged by a merge.
+
+// Std
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+// External
+use anyhow::Result;
+use colored::*;
+
+// Internal
+use crate::util::head::Head;
+use crate::util::index::{read_index, Conflict, Extensions, STAGE_BASE, STAGE_OURS, STAGE_THEIRS};
+use crate::util::revision::head_hash;
+use crate::util::tree;
+use nss_core::repository::NssRepository;
+use nss_core::struct_set::{DIffTag, Index};
+
+/// Changes found between HEAD, the index and the working directory.
+#[derive(Default)]
+pub struct Status {
+    /// Snapped but not registered yet (HEAD -> index)
+    pub staged: Vec<(DIffTag, PathBuf)>,
+    /// Tracked files edited after the last snap (index -> working)
+    pub modified: Vec<PathBuf>,
+    /// Tracked files removed after the last snap (index -> working)
+    pub deleted: Vec<PathBuf>,
+    /// Files never snapped
+    pub untracked: Vec<PathBuf>,
+    /// Conflicted by a merge and not snapped yet
+    pub unmerged: Vec<PathBuf>,
+}
+
+impl Status {
+    pub fn is_clean(&self) -> bool {
+        self.staged.is_empty()
+            && self.modified.is_empty()
+            && self.deleted.is_empty()
+            && self.untracked.is_empty()
+            && self.unmerged.is_empty()
+    }
+}
+
+pub fn run(repository: &NssRepository) -> Result<()> {
+    let status = collect(repository)?;
+
+    match Head::read(repository)? {
+        Head::Detached(hash) => println!("HEAD detached at {}", &hash[0..7].yellow()),
+        head => println!(
+            "On bookmark {}",
+            head.bookmarker().unwrap_or(head.value()).bright_green()
+        ),
+    }
+
+    if !status.unmerged.is_empty() {
+        let conflicts = Extensions::read(repository)?.conflicts()?;
+        println!("\nUnmerged paths:");
+        for path in &status.unmerged {
+            let line = format!(
+                "    {:<16}{}",
+                unmerged_state(&conflicts, path),
+                path.display()
+            );
+            println!("{}", line.red());
+        }
+    }
+
+    if !status.staged.is_empty() {
+        println!("\nChanges to be registered:");
+        for (tag, path) in &status.staged {
+            let line = match tag {
+                DIffTag::Insert => format!("    new file:   {}", path.display()),
+                DIffTag::Delete => format!("    deleted:    {}", path.display()),
+                _ => format!("    modified:   {}", path.display()),
+            };
+            println!("{}", line.green());
+        }
+    }
+
+    if !(status.modified.is_empty() && status.deleted.is_empty()) {
+        println!("\nChanges not snapped:");
+        for path in &status.modified {
+            println!("{}", format!("    modified:   {}", path.display()).red());
+        }
+        for path in &status.deleted {
+            println!("{}", format!("    deleted:    {}", path.display()).red());
+        }
+    }
+
+    if !status.untracked.is_empty() {
+        println!("\nUntracked files:");
+        for path in &status.untracked {
+            println!("{}", format!("    {}", path.display()).red());
+        }
+    }
+
+    if status.is_clean() {
+        println!("\nNothing to snap, working directory clean");
+    }
+
+    Ok(())
+}
+
+/// Three-way comparison of HEAD tree, index and working directory.
+pub fn collect(repository: &NssRepository) -> Result<Status> {
+    let head_index = match head_hash(repository)? {
+        Some(hash) => tree::to_index(repository, &hash)?,
+        None => Index::empty(),
+    };
+    let index = read_index(repository)?;
+    let working_index = Index::new_all(repository)?;
+
+    let mut status = Status::default();
+
+    // Unmerged path is shown only as unmerged
+    let unmerged = Extensions::read(repository)?
+        .conflicts()?
+        .into_iter()
+        .map(|c| c.path)
+        .collect::<BTreeSet<PathBuf>>();
+
+    for (tag, path) in head_index.diff(index.clone()) {
+        if unmerged.contains(&path) {
+            continue;
+        }
+        match tag {
+            DIffTag::Insert | DIffTag::Delete | DIffTag::Replace => status.staged.push((tag, path)),
+            _ => (),
+        }
+    }
+
+    for (tag, path) in index.diff(working_index) {
+        if unmerged.contains(&path) {
+            continue;
+        }
+        match tag {
+            DIffTag::Insert => status.untracked.push(path),
+            DIffTag::Delete => status.deleted.push(path),
+            DIffTag::Replace => status.modified.push(path),
+            _ => (),
+        }
+    }
+    status.unmerged = unmerged.into_iter().collect();
+
+    Ok(status)
+}
+
+/// How both sides changed the unmerged path, from its stages.
+fn unmerged_state(conflicts: &[Conflict], path: &Path) -> &'static str {
+    let has = |stage| conflicts.iter().any(|c| c.path == path && c.stage == stage);
+
+    match (has(STAGE_BASE), has(STAGE_OURS), has(STAGE_THEIRS)) {
+        (_, true, false) => "deleted by them:",
+        (_, false, true) => "deleted by us:",
+        (false, _, _) => "both added:",
+        _ => "both modified:",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::{snap, voyage};
+    use std::fs;
+    use testdir::testdir;
+
+    #[test]
+    fn test_collect() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        // Untracked file
+        fs::write(temp_dir.join("first.rs"), "fn main() {}").unwrap();
+        let status = collect(&test_repo).unwrap();
+        assert_eq!(status.untracked, vec![PathBuf::from("first.rs")]);
+        assert!(status.staged.is_empty());
+
+        // Staged file
+        snap::shot(&test_repo, temp_dir.join("first.rs").to_str().unwrap()).unwrap();
+        let status = collect(&test_repo).unwrap();
+        assert!(status.untracked.is_empty());
+        assert_eq!(status.staged.len(), 1);
+        assert_eq!(status.staged[0].1, PathBuf::from("first.rs"));
+
+        // Modified after snap
+        fs::write(temp_dir.join("first.rs"), "fn main() { todo!() }").unwrap();
+        let status = collect(&test_repo).unwrap();
+        assert_eq!(status.modified, vec![PathBuf::from("first.rs")]);
+
+        // Deleted after snap
+        fs::remove_file(temp_dir.join("first.rs")).unwrap();
+        let status = collect(&test_repo).unwrap();
+        assert_eq!(status.deleted, vec![PathBuf::from("first.rs")]);
+
+        // Broken index is an error, not every file deleted
+        fs::write(temp_dir.join(".nss").join("INDEX"), "broken").unwrap();
+        assert!(collect(&test_repo).is_err());
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+}
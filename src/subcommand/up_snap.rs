@@ -1,7 +1,8 @@
 //! **Update-index command** Base command: `git update-index
 
 // Std
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::path::PathBuf;
 
 // External
@@ -9,29 +10,109 @@ use anyhow::Result;
 use colored::*;
 
 // Internal
+use crate::util::cache_tree::CachedTree;
+use crate::util::index::{read_index, write_index_with, Extensions};
+use crate::util::{object, pool, stat};
 use nss_core::repository::NssRepository;
-use nss_core::struct_set::Index;
+use nss_core::struct_set::{FileMeta, Index};
 
 pub fn run(repository: &NssRepository, file_path: &str) -> Result<()> {
-    let mut index = match repository.read_index() {
-        Ok(index) => index,
-        Err(e) => {
-            println!("{}", e);
-            Index::empty()
-        }
-    };
+    let path = repository.path().join(file_path);
+
+    // Stat before the file is streamed into the hash
+    let metadata = fs::metadata(&path)?;
+    let hash = object::hash_blob(&path)?;
+
+    add_entry(
+        repository,
+        stat::filemeta(repository, &path, &metadata, hash)?,
+    )
+}
+
+/// Put the entry into the index, replacing the one of the same path.
+pub fn add_entry(repository: &NssRepository, filemeta: FileMeta) -> Result<()> {
+    let mut extensions = Extensions::read(repository)?;
+    let mut index = read_index(repository)?;
 
-    index.add(repository, &repository.path().join(file_path), None)?;
+    // Snapping the file marks its conflict as resolved
+    let mut cache = CachedTree::from_extensions(&extensions)?;
+    cache.invalidate(&filemeta.filename);
+    cache.store(&mut extensions);
+    extensions.resolve(Some(&filemeta.filename))?;
 
-    repository.write_index(index)?;
+    match index
+        .filemetas
+        .iter()
+        .position(|f| f.filename == filemeta.filename)
+    {
+        Some(i) => index.filemetas[i] = filemeta,
+        None => {
+            index.filemetas.push(filemeta);
+            index.filemetas.sort_by(|a, b| a.filename.cmp(&b.filename));
+        }
+    }
+
+    write_index_with(repository, index, &extensions)?;
 
     Ok(())
 }
 
 pub fn run_all(repository: &NssRepository) -> Result<()> {
-    let index = Index::new_all(repository)?;
+    update_all(repository, false)
+}
+
+/// Rebuild the index from the working tree.
+///
+/// Files whose stat data is unchanged keep their index entry without being
+/// read. Blobs of the rehashed files are also written if `write_blob` is set.
+pub fn update_all(repository: &NssRepository, write_blob: bool) -> Result<()> {
+    let mut extensions = Extensions::read(repository)?;
+    let old_index = read_index(repository)?;
+    let index_mtime = stat::index_mtime(repository);
+    let tracked = old_index
+        .filemetas
+        .iter()
+        .enumerate()
+        .map(|(i, f)| (f.filename.clone(), i))
+        .collect::<HashMap<PathBuf, usize>>();
+
+    let mut index = Index::empty();
+    let mut changed = vec![];
+    for file_path in repository.get_all_paths_ignore(repository.path()) {
+        let relative = file_path.strip_prefix(repository.path())?;
+        if let Some(&i) = tracked.get(relative) {
+            let filemeta = &old_index.filemetas[i];
+            if stat::is_unchanged(filemeta, &fs::metadata(&file_path)?, index_mtime) {
+                index.filemetas.push(filemeta.clone());
+                continue;
+            }
+        }
+        changed.push(file_path);
+    }
 
-    repository.write_index(index)?;
+    // Hash (and compress) on all cores, any failure aborts before the index is written
+    let filemetas = pool::parallel_map(&changed, |file_path| {
+        let metadata = fs::metadata(file_path)?;
+        let hash = match write_blob {
+            true => object::store_blob(repository, file_path)?,
+            false => object::hash_blob(file_path)?,
+        };
+        stat::filemeta(repository, file_path, &metadata, hash)
+    })
+    .into_iter()
+    .collect::<Result<Vec<FileMeta>>>()?;
+    index.filemetas.extend(filemetas);
+    index.filemetas.sort_by(|a, b| a.filename.cmp(&b.filename));
+
+    // Only directories containing changed files are invalidated
+    let mut cache = CachedTree::from_extensions(&extensions)?;
+    for (_, path) in old_index.diff(index.clone()) {
+        cache.invalidate(&path);
+    }
+    cache.store(&mut extensions);
+    extensions.resolve(None)?;
+
+    write_index_with(repository, index, &extensions)?;
 
     Ok(())
 }
@@ -69,14 +150,111 @@ pub fn run_option_v(repository: &NssRepository) -> Result<()> {
 
 #[cfg(test)]
 mod tests {
-    // use super::*;
+    use super::*;
+    use crate::subcommand::voyage;
+    use std::path::Path;
+    use std::thread;
+    use std::time::Duration;
+    use testdir::testdir;
 
     #[test]
-    fn test_run() {}
+    fn test_run() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        fs::create_dir(temp_dir.join("src")).unwrap();
+        fs::write(temp_dir.join("src").join("second.rs"), "second").unwrap();
+        fs::write(temp_dir.join("first.rs"), "first").unwrap();
+        run(&test_repo, "src/second.rs").unwrap();
+        run(&test_repo, "first.rs").unwrap();
+
+        let index = test_repo.read_index().unwrap();
+        let filenames = index
+            .filemetas
+            .iter()
+            .map(|f| f.filename.clone())
+            .collect::<Vec<PathBuf>>();
+        assert_eq!(
+            filenames,
+            vec![PathBuf::from("first.rs"), PathBuf::from("src/second.rs")]
+        );
+        assert_eq!(
+            index.filemetas[0].hash,
+            object::hash_blob(&temp_dir.join("first.rs")).unwrap()
+        );
+
+        // Entry of the same path is replaced
+        fs::write(temp_dir.join("first.rs"), "first changed").unwrap();
+        run(&test_repo, "first.rs").unwrap();
+        let index = test_repo.read_index().unwrap();
+        assert_eq!(index.filemetas.len(), 2);
+        assert_eq!(
+            index.filemetas[0].hash,
+            object::hash_blob(&temp_dir.join("first.rs")).unwrap()
+        );
+        assert_eq!(index.filemetas[0].filesize, 13);
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
 
     #[test]
     fn test_run_all() {}
 
+    #[test]
+    fn test_update_all() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        // Written a second before the index, so not racily clean
+        fs::write(temp_dir.join("first.rs"), "first").unwrap();
+        fs::write(temp_dir.join("second.rs"), "second").unwrap();
+        thread::sleep(Duration::from_millis(1100));
+        update_all(&test_repo, true).unwrap();
+        let hash = |name: &str| {
+            let index = test_repo.read_index().unwrap();
+            let filemeta = index
+                .filemetas
+                .iter()
+                .find(|f| f.filename == Path::new(name))
+                .unwrap();
+            hex::encode(&filemeta.hash)
+        };
+        let first = hash("first.rs");
+        let second = hash("second.rs");
+        assert!(test_repo.objects_path(&first).exists());
+
+        // Unchanged file is not read, so its removed blob is not written again
+        fs::remove_file(test_repo.objects_path(&first)).unwrap();
+        fs::write(temp_dir.join("second.rs"), "second changed").unwrap();
+        update_all(&test_repo, true).unwrap();
+
+        assert_eq!(hash("first.rs"), first);
+        assert!(!test_repo.objects_path(&first).exists());
+        let changed = hash("second.rs");
+        assert_ne!(changed, second);
+        assert_eq!(
+            changed,
+            hex::encode(object::hash_blob(&temp_dir.join("second.rs")).unwrap())
+        );
+        assert!(test_repo.objects_path(&changed).exists());
+
+        // Broken index is not rebuilt from scratch
+        let index_path = temp_dir.join(".nss").join("INDEX");
+        fs::write(&index_path, "broken").unwrap();
+        assert!(update_all(&test_repo, true).is_err());
+        assert_eq!(fs::read_to_string(&index_path).unwrap(), "broken");
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
     #[test]
     fn test_run_option_w() {}
 }
@@ -1,81 +1,152 @@
 //! **update-marker command** Base command: `git update-ref`
 //!
-//! /// TODO: Documentation
-
-// Std
-use std::fs::OpenOptions;
-use std::io::prelude::*;
+//! Move HEAD (or the bookmark with `-b`) to the commit. When the old commit
+//! is given, it is moved only if it still points to the old commit, so an
+//! update by another process meanwhile is not overwritten.
+//!
+//! **Note:** The old commit of 40 zeros means no commit yet.
 
 // External
 use anyhow::{bail, Result};
 
 // Internal
+use crate::util::head::Head;
+use crate::util::refs::{self, Expected, RefTransaction};
+use crate::util::revision;
 use nss_core::repository::NssRepository;
 
-pub fn run(repository: &NssRepository, new_commit: &str) -> Result<()> {
-    let object = repository.read_object(new_commit)?;
-    if object.as_str() == "commit" {
-        let mut file = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(repository.head_path())?;
-
-        file.write_all(format!("bookmarker: {}", new_commit).as_bytes())?;
-    } else {
-        bail!("Not commit hash ({})", new_commit)
-    }
+/// Old commit meaning the reference has no commit yet
+const NO_COMMIT: &str = "0000000000000000000000000000000000000000";
 
-    Ok(())
+pub fn run(repository: &NssRepository, new_commit: &str, old_commit: Option<&str>) -> Result<()> {
+    let new_commit = &resolve_commit(repository, new_commit)?;
+
+    // Old commit of a bookmark can't be checked through HEAD
+    let expected = match (Head::read(repository)?, old_commit) {
+        (_, None) => Expected::Any,
+        (Head::Detached(_), Some(old)) => expected(repository, old)?,
+        (head, Some(_)) => bail!(
+            "HEAD is on {}, so check its old commit with -b {}",
+            head.value(),
+            head.bookmarker().unwrap_or(head.value())
+        ),
+    };
+
+    RefTransaction::new(repository, "update-ref")
+        .update(refs::HEAD, expected, new_commit)
+        .commit()
 }
 
-#[allow(dead_code)]
+/// Move the bookmark, only if it still points to the old commit when given.
 pub fn run_option_b(
     repository: &NssRepository,
     bookmarker: &str,
     new_commit: &str,
     old_commit: Option<&str>,
 ) -> Result<()> {
-    let object = repository.read_object(new_commit)?;
-    if object.as_str() == "commit" {
-        let mut file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .truncate(true)
-            .open(repository.bookmarks_path(bookmarker))?;
-        let mut bookmark_hash = String::new();
-        file.read_to_string(&mut bookmark_hash)?;
-
-        if bookmark_hash.is_empty() {
-            file.write_all(new_commit.as_bytes())?;
-        } else if let Some(commit) = old_commit {
-            if bookmark_hash == commit {
-                file.write_all(new_commit.as_bytes())?;
-            } else {
-                bail!(
-                    "This bookmarker has the difference old hash ({})",
-                    bookmark_hash
-                );
-            }
-        } else {
-            bail!(
-                "This bookmarker has the difference old hash ({})",
-                bookmark_hash
-            );
-        }
-    } else {
-        bail!("Not commit hash <new commit> ({})", new_commit)
+    refs::check_bookmarker(bookmarker)?;
+    let new_commit = &resolve_commit(repository, new_commit)?;
+    let expected = match old_commit {
+        Some(old) => expected(repository, old)?,
+        None => Expected::Any,
+    };
+
+    RefTransaction::new(repository, "update-ref")
+        .update(&refs::local_path(bookmarker), expected, new_commit)
+        .commit()
+}
+
+fn resolve_commit(repository: &NssRepository, commit: &str) -> Result<String> {
+    let hash = revision::resolve(repository, commit)?;
+    if repository.read_object(&hash)?.as_str() != "commit" {
+        bail!("Not commit hash ({})", hash)
     }
 
-    Ok(())
+    Ok(hash)
+}
+
+fn expected(repository: &NssRepository, old_commit: &str) -> Result<Expected> {
+    match old_commit {
+        NO_COMMIT => Ok(Expected::Empty),
+        old => Ok(Expected::Value(resolve_commit(repository, old)?)),
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    // use super::*;
+    use super::*;
+    use crate::subcommand::voyage;
+    use crate::util::fixture::write_commit;
+    use std::fs;
+    use testdir::testdir;
 
     #[test]
-    fn test_run() {}
+    fn test_run() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        let first = write_commit(&test_repo, &[], "first");
+        let second = write_commit(&test_repo, &[&first], "second");
+        fs::write(test_repo.bookmarks_path("voyage"), &first).unwrap();
+
+        // Old commit is checked through the bookmark
+        assert!(run(&test_repo, &second, Some(&first)).is_err());
+
+        run(&test_repo, &first, None).unwrap();
+        assert_eq!(
+            Head::read(&test_repo).unwrap(),
+            Head::Detached(first.clone())
+        );
+
+        // HEAD was moved from the old commit
+        assert!(run(&test_repo, &first, Some(&second)).is_err());
+        run(&test_repo, &second, Some(&first[0..7])).unwrap();
+        assert_eq!(Head::read(&test_repo).unwrap(), Head::Detached(second));
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
 
     #[test]
-    fn test_run_option_b() {}
+    fn test_run_option_b() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        let first = write_commit(&test_repo, &[], "first");
+        let second = write_commit(&test_repo, &[&first], "second");
+        let bookmark = |name: &str| {
+            fs::read_to_string(test_repo.bookmarks_path(name))
+                .unwrap()
+                .trim()
+                .to_string()
+        };
+
+        // No commit yet
+        assert!(run_option_b(&test_repo, "voyage", &first, Some(&second)).is_err());
+        run_option_b(&test_repo, "voyage", &first, Some(NO_COMMIT)).unwrap();
+        assert_eq!(bookmark("voyage"), first);
+
+        // Moved by another process meanwhile
+        assert!(run_option_b(&test_repo, "voyage", &second, Some(NO_COMMIT)).is_err());
+        assert!(run_option_b(&test_repo, "voyage", &second, Some(&second)).is_err());
+        assert_eq!(bookmark("voyage"), first);
+
+        run_option_b(&test_repo, "voyage", &second, Some(&first)).unwrap();
+        assert_eq!(bookmark("voyage"), second);
+        run_option_b(&test_repo, "voyage", &first, None).unwrap();
+        assert_eq!(bookmark("voyage"), first);
+
+        // Invalid name and not commit
+        assert!(run_option_b(&test_repo, "../config", &first, None).is_err());
+        assert!(run_option_b(&test_repo, "voyage", "c192349d0ee5", None).is_err());
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
 }
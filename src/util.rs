@@ -0,0 +1,21 @@
+pub mod cache_tree;
+pub mod date;
+#[cfg(test)]
+pub mod fixture;
+pub mod graph;
+pub mod head;
+pub mod index;
+pub mod line_diff;
+pub mod lock;
+pub mod object;
+pub mod parents;
+pub mod pool;
+pub mod pretty;
+pub mod reflog;
+pub mod refs;
+pub mod revision;
+pub mod stat;
+pub mod three_way;
+pub mod tree;
+pub mod walk;
+pub mod worktree;
@@ -0,0 +1,162 @@
+//! Cached tree extension of the index (like git's `TREE` extension).
+//!
+//! Tree hash and entry count (files under it) of each directory whose
+//! files in the index are unchanged since the tree was written. Every
+//! update of the index invalidates the directories containing the path,
+//! so only the invalidated spine of the tree is recomputed.
+//!
+//! **Note:** Stored as the `TREE` extension of the index, one directory per
+//! line (`<entry count> <tree hash> <directory>`, the root is `.`), so it is
+//! ignored as soon as INDEX is written without it.
+
+// Std
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// External
+use anyhow::{bail, Context, Result};
+
+// Internal
+use super::index::{write_extensions, Extensions};
+use nss_core::repository::NssRepository;
+
+/// Name of the extension
+const TREE: &str = "TREE";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CachedTree {
+    trees: HashMap<PathBuf, (usize, String)>,
+}
+
+impl CachedTree {
+    /// Read the extension of the current index, empty if it does not exist.
+    pub fn read(repository: &NssRepository) -> Result<Self> {
+        Self::from_extensions(&Extensions::read(repository)?)
+    }
+
+    pub fn from_extensions(extensions: &Extensions) -> Result<Self> {
+        let mut trees = HashMap::new();
+        for line in extensions.get(TREE).unwrap_or_default().lines() {
+            let mut items = line.splitn(3, ' ');
+            let (Some(count), Some(hash), Some(dir)) = (items.next(), items.next(), items.next())
+            else {
+                bail!("Cached tree is broken ({})", line);
+            };
+            let count = count
+                .parse::<usize>()
+                .with_context(|| format!("Cached tree is broken ({})", line))?;
+            let dir = match dir {
+                "." => PathBuf::new(),
+                d => PathBuf::from(d),
+            };
+            trees.insert(dir, (count, hash.to_string()));
+        }
+
+        Ok(Self { trees })
+    }
+
+    /// Put the extension into the extensions written with the next index.
+    pub fn store(&self, extensions: &mut Extensions) {
+        let mut lines = self
+            .trees
+            .iter()
+            .map(|(dir, (count, hash))| {
+                let dir = match dir.as_os_str().is_empty() {
+                    true => ".".to_string(),
+                    false => dir.display().to_string(),
+                };
+                format!("{} {} {}\n", count, hash, dir)
+            })
+            .collect::<Vec<String>>();
+        lines.sort();
+
+        extensions.set(TREE, lines.concat());
+    }
+
+    /// Write the extension for the current index, computed from `extensions`
+    /// read with it. Nothing is written if the index was replaced meanwhile.
+    pub fn write(&self, repository: &NssRepository, mut extensions: Extensions) -> Result<()> {
+        self.store(&mut extensions);
+
+        write_extensions(repository, &extensions)
+    }
+
+    /// Tree hash and entry count of the directory, if still valid.
+    pub fn get(&self, dir: &Path) -> Option<(usize, &str)> {
+        self.trees
+            .get(dir)
+            .map(|(count, hash)| (*count, hash.as_str()))
+    }
+
+    pub fn insert(&mut self, dir: &Path, count: usize, hash: &str) {
+        self.trees
+            .insert(dir.to_path_buf(), (count, hash.to_string()));
+    }
+
+    /// Invalidate every directory containing the path (from the repository root).
+    pub fn invalidate(&mut self, path: &Path) {
+        for dir in path.ancestors().skip(1) {
+            self.trees.remove(dir);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::voyage;
+    use crate::util::index::write_index;
+    use nss_core::struct_set::Index;
+    use std::fs;
+    use testdir::testdir;
+
+    #[test]
+    fn test_cached_tree() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        let file_path = temp_dir.join("first.rs");
+        fs::write(&file_path, "first").unwrap();
+        let mut index = Index::empty();
+        index.add(&test_repo, &file_path, None).unwrap();
+        write_index(&test_repo, index.clone()).unwrap();
+
+        // No extension yet
+        let mut cache = CachedTree::read(&test_repo).unwrap();
+        assert_eq!(cache, CachedTree::default());
+
+        cache.insert(Path::new(""), 3, &"0".repeat(40));
+        cache.insert(Path::new("src"), 2, &"1".repeat(40));
+        cache.insert(Path::new("src/sub"), 1, &"2".repeat(40));
+        cache.insert(Path::new("tests"), 1, &"3".repeat(40));
+        cache
+            .write(&test_repo, Extensions::read(&test_repo).unwrap())
+            .unwrap();
+        assert_eq!(CachedTree::read(&test_repo).unwrap(), cache);
+
+        // Only the spine to the file is invalidated
+        cache.invalidate(Path::new("src/a.rs"));
+        assert!(cache.get(Path::new("")).is_none());
+        assert!(cache.get(Path::new("src")).is_none());
+        assert_eq!(cache.get(Path::new("src/sub")).unwrap().0, 1);
+        assert_eq!(cache.get(Path::new("tests")).unwrap().0, 1);
+
+        // Index replaced without the extension
+        write_index(&test_repo, index.clone()).unwrap();
+        assert_eq!(CachedTree::read(&test_repo).unwrap(), CachedTree::default());
+
+        // Extensions read before the index was replaced are not written
+        let extensions = Extensions::read(&test_repo).unwrap();
+        fs::write(&file_path, "changed").unwrap();
+        index.add(&test_repo, &file_path, None).unwrap();
+        test_repo.write_index(index).unwrap();
+        cache.write(&test_repo, extensions).unwrap();
+        assert_eq!(CachedTree::read(&test_repo).unwrap(), CachedTree::default());
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+}
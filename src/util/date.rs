@@ -0,0 +1,114 @@
+//! Date expressions given to options such as `--since`.
+//!
+//! - `2023-06-01T12:00:00+09:00`: RFC 3339
+//! - `2023-06-01 12:00:00`, `2023-06-01 12:00`, `2023-06-01`: local time
+//! - `2.days.ago`, `3 hours ago`: relative to now (`second`, `minute`,
+//!   `hour`, `day`, `week`, `month` as 30 days and `year` as 365 days)
+
+// External
+use anyhow::{bail, Result};
+use chrono::prelude::Datelike;
+use chrono::{DateTime, Local, Month, NaiveDate, NaiveDateTime, TimeZone};
+
+/// Parse the date expression into unix timestamp.
+pub fn parse_timestamp(expr: &str) -> Result<i64> {
+    let expr = expr.trim();
+
+    if let Ok(date) = DateTime::parse_from_rfc3339(expr) {
+        return Ok(date.timestamp());
+    }
+
+    if let Some(timestamp) = parse_relative(expr, Local::now().timestamp()) {
+        return Ok(timestamp);
+    }
+
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(expr, f).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(expr, "%Y-%m-%d")
+                .ok()
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+        });
+
+    match naive.and_then(|n| Local.from_local_datetime(&n).earliest()) {
+        Some(date) => Ok(date.timestamp()),
+        None => bail!("Invalid date: {}", expr),
+    }
+}
+
+/// Parse `<n>.<unit>.ago` or `<n> <unit> ago` as the time before now.
+fn parse_relative(expr: &str, now: i64) -> Option<i64> {
+    let items = expr
+        .split(['.', ' '])
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<&str>>();
+
+    match items.as_slice() {
+        [number, unit, "ago"] => {
+            let seconds = match unit.trim_end_matches('s') {
+                "second" | "sec" => 1,
+                "minute" | "min" => 60,
+                "hour" => 60 * 60,
+                "day" => 24 * 60 * 60,
+                "week" => 7 * 24 * 60 * 60,
+                "month" => 30 * 24 * 60 * 60,
+                "year" => 365 * 24 * 60 * 60,
+                _ => return None,
+            };
+            Some(now - number.parse::<i64>().ok()? * seconds)
+        }
+        _ => None,
+    }
+}
+
+/// Format unix timestamp in local time (e.g. `Thu Jun 01 12:00:00 2023 +0900`).
+pub fn format_local(timestamp: i64) -> String {
+    let timestamp = Local.timestamp_opt(timestamp, 0).unwrap();
+
+    format!(
+        "{} {:.3} {}",
+        timestamp.weekday(),
+        Month::try_from(timestamp.month() as u8).unwrap().name(),
+        timestamp.format("%d %H:%M:%S %Y %z")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(
+            parse_timestamp("2023-06-01T12:00:00+09:00").unwrap(),
+            1685588400
+        );
+
+        let day = parse_timestamp("2023-06-01").unwrap();
+        assert_eq!(parse_timestamp("2023-06-01 00:00").unwrap(), day);
+        assert_eq!(parse_timestamp("2023-06-01 00:00:30").unwrap(), day + 30);
+
+        assert!(parse_timestamp("yesterday").is_err());
+        assert!(parse_timestamp("2023-13-01").is_err());
+    }
+
+    #[test]
+    fn test_parse_relative() {
+        let now = 1685588400;
+        assert_eq!(parse_relative("2.days.ago", now), Some(now - 172800));
+        assert_eq!(parse_relative("1 hour ago", now), Some(now - 3600));
+        assert_eq!(parse_relative("30.minutes.ago", now), Some(now - 1800));
+        assert_eq!(parse_relative("2.days", now), None);
+        assert_eq!(parse_relative("two.days.ago", now), None);
+        assert_eq!(parse_relative("2.fortnights.ago", now), None);
+
+        assert!(parse_timestamp("1.week.ago").is_ok());
+    }
+
+    #[test]
+    fn test_format_local() {
+        let timestamp = parse_timestamp("2023-06-01 12:34:56").unwrap();
+        assert!(format_local(timestamp).starts_with("Thu Jun 01 12:34:56 2023 "));
+    }
+}
@@ -0,0 +1,22 @@
+//! Objects written by the tests.
+
+// Internal
+use super::parents::Parents;
+use nss_core::repository::NssRepository;
+use nss_core::struct_set::{Commit, Hashable};
+
+/// Write the commit of an empty-looking tree and return its hash.
+pub fn write_commit(repository: &NssRepository, parents: &[&str], message: &str) -> String {
+    let commit = Commit::new(
+        "c192349d0ee530038e5d925fdd701652ca755ba8".to_string(),
+        Parents::new(parents.iter().map(|p| p.to_string()).collect()).encode(),
+        "noshishi\0 ".to_string(),
+        "noshishi\0 ".to_string(),
+        message.to_string(),
+    )
+    .unwrap();
+    let hash = hex::encode(commit.to_hash());
+    repository.write_object(commit).unwrap();
+
+    hash
+}
@@ -0,0 +1,214 @@
+//! Text graph of the commit history (like `git log --graph`).
+//!
+//! Each lane is a column waiting for a commit. A commit takes its lane
+//! (`*`), and the lane continues to the first parent. Other parents of
+//! merge commit open new lanes (`\`), and lanes waiting for the same
+//! commit join together before it (`/`).
+//!
+//! **Note:** Commits must be given in topological order.
+
+/// Graph rows drawn around one commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphRows {
+    /// Lanes joining into the commit
+    pub before: Vec<String>,
+    /// Row of the commit itself
+    pub commit: String,
+    /// Lanes branching to the parents
+    pub after: Vec<String>,
+    /// Prefix for the other lines of the commit
+    pub padding: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Graph {
+    columns: Vec<String>,
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn next(&mut self, hash: &str, parents: &[String]) -> GraphRows {
+        let idx = match self.columns.iter().position(|c| c == hash) {
+            Some(i) => i,
+            None => {
+                self.columns.push(hash.to_string());
+                self.columns.len() - 1
+            }
+        };
+
+        // Other lanes waiting for the same commit join into it
+        let mut before = vec![];
+        let joining = self
+            .columns
+            .iter()
+            .enumerate()
+            .filter(|(j, c)| *j != idx && *c == hash)
+            .map(|(j, _)| j)
+            .collect::<Vec<usize>>();
+        if !joining.is_empty() {
+            let old = std::mem::take(&mut self.columns);
+            let mut edges = vec![];
+            for (j, lane) in old.iter().enumerate() {
+                if joining.contains(&j) {
+                    edges.push((j, idx));
+                } else {
+                    edges.push((j, self.columns.len()));
+                    self.columns.push(lane.clone());
+                }
+            }
+            let width = lanes_width(old.len());
+            before.push(pad(render(&edges), width));
+        }
+
+        let old = self.columns.clone();
+        let commit = old
+            .iter()
+            .enumerate()
+            .map(|(j, _)| if j == idx { "*" } else { "|" })
+            .collect::<Vec<&str>>()
+            .join(" ");
+
+        // Lanes after the commit
+        let mut new: Vec<String> = vec![];
+        let mut edges = vec![];
+        for (j, lane) in old.iter().enumerate() {
+            if j != idx {
+                edges.push((j, new.len()));
+                new.push(lane.clone());
+                continue;
+            }
+            for (n, parent) in parents.iter().enumerate() {
+                // Parent already waited on another lane
+                if n > 0 && old.contains(parent) {
+                    continue;
+                }
+                edges.push((j, new.len()));
+                new.push(parent.clone());
+            }
+        }
+        for parent in parents.iter().skip(1) {
+            if old.contains(parent) {
+                if let Some(pos) = new.iter().position(|c| c == parent) {
+                    edges.push((idx, pos));
+                }
+            }
+        }
+        self.columns = new;
+
+        let width = lanes_width(old.len().max(self.columns.len()));
+        let after = match edges.iter().all(|(a, b)| a == b) {
+            true => vec![],
+            false => vec![pad(render(&edges), width)],
+        };
+        let padding = pad(
+            self.columns
+                .iter()
+                .map(|_| "|")
+                .collect::<Vec<&str>>()
+                .join(" "),
+            width,
+        );
+
+        GraphRows {
+            before,
+            commit: pad(commit, width),
+            after,
+            padding,
+        }
+    }
+}
+
+/// Characters needed for the lanes and a space before the text.
+fn lanes_width(lanes: usize) -> usize {
+    lanes.max(1) * 2
+}
+
+fn pad(row: String, width: usize) -> String {
+    format!("{:<width$}", row, width = width)
+}
+
+/// Draw edges from lane `a` (old row) to lane `b` (new row).
+fn render(edges: &[(usize, usize)]) -> String {
+    let width = edges
+        .iter()
+        .map(|(a, b)| a.max(b) * 2 + 2)
+        .max()
+        .unwrap_or(0);
+    let mut row = vec![' '; width];
+    for &(a, b) in edges {
+        match a.cmp(&b) {
+            std::cmp::Ordering::Equal => row[a * 2] = '|',
+            std::cmp::Ordering::Less => row[a * 2 + 1] = '\\',
+            std::cmp::Ordering::Greater => row[a * 2 - 1] = '/',
+        }
+    }
+
+    row.into_iter().collect::<String>().trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw(commits: &[(&str, &[&str])]) -> Vec<String> {
+        let mut graph = Graph::new();
+        let mut lines = vec![];
+        for (hash, parents) in commits {
+            let parents = parents.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+            let rows = graph.next(hash, &parents);
+            lines.extend(rows.before.into_iter().map(|r| r.trim_end().to_string()));
+            lines.push(format!("{}{}", rows.commit, hash));
+            lines.extend(rows.after.into_iter().map(|r| r.trim_end().to_string()));
+        }
+
+        lines
+    }
+
+    #[test]
+    fn test_linear() {
+        let lines = draw(&[("c", &["b"]), ("b", &["a"]), ("a", &[])]);
+        assert_eq!(lines, vec!["* c", "* b", "* a"]);
+    }
+
+    #[test]
+    fn test_merge() {
+        //   root - a1 - a2 - merge
+        //       \           /
+        //        b1 -------
+        let lines = draw(&[
+            ("merge", &["a2", "b1"]),
+            ("a2", &["a1"]),
+            ("b1", &["root"]),
+            ("a1", &["root"]),
+            ("root", &[]),
+        ]);
+        assert_eq!(
+            lines,
+            vec![
+                "*   merge",
+                "|\\",
+                "* | a2",
+                "| * b1",
+                "* | a1",
+                "|/",
+                "* root"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_padding() {
+        let mut graph = Graph::new();
+        let rows = graph.next("merge", &["a".to_string(), "b".to_string()]);
+        assert_eq!(rows.padding, "| | ");
+        assert_eq!(rows.after, vec!["|\\  "]);
+
+        let rows = graph.next("a", &[]);
+        assert_eq!(rows.commit, "* | ");
+        assert_eq!(rows.after, vec![" /  "]);
+        assert_eq!(rows.padding, "|   ");
+    }
+}
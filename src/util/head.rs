@@ -0,0 +1,124 @@
+//! HEAD: the bookmark being on, or a commit directly (detached).
+//!
+//! - `bookmarker: bookmarks/local/<bookmarker>`: on the bookmark (symbolic)
+//! - `<commit hash>`: detached
+//!
+//! **Note:** Detached HEAD written as `bookmarker: <commit hash>` by older
+//! versions is also read.
+
+// Std
+use std::fs;
+
+// External
+use anyhow::{bail, Context, Result};
+
+// Internal
+use super::refs::{HEAD, LOCAL_PREFIX};
+use nss_core::repository::NssRepository;
+
+const SYMBOLIC_PREFIX: &str = "bookmarker: ";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Head {
+    /// Path from `.nss` of the bookmark (e.g. `bookmarks/local/voyage`)
+    Symbolic(String),
+    /// Commit hash
+    Detached(String),
+}
+
+impl Head {
+    pub fn read(repository: &NssRepository) -> Result<Self> {
+        let content = fs::read_to_string(repository.head_path())
+            .with_context(|| "Can't read HEAD".to_string())?;
+
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let content = content.trim();
+        let item = content.strip_prefix(SYMBOLIC_PREFIX).unwrap_or(content);
+
+        if item.starts_with("bookmarks/") {
+            Ok(Self::Symbolic(item.to_string()))
+        } else if item.len() == 40 && item.chars().all(|c| c.is_ascii_hexdigit()) {
+            Ok(Self::Detached(item.to_string()))
+        } else {
+            bail!("HEAD is broken ({})", content)
+        }
+    }
+
+    /// HEAD of the value: bookmark path from `.nss` or commit hash.
+    pub fn from_value(value: &str) -> Self {
+        match value.starts_with("bookmarks/") {
+            true => Self::Symbolic(value.to_string()),
+            false => Self::Detached(value.to_string()),
+        }
+    }
+
+    /// Bookmark path from `.nss` or commit hash.
+    pub fn value(&self) -> &str {
+        match self {
+            Self::Symbolic(path) => path,
+            Self::Detached(hash) => hash,
+        }
+    }
+
+    /// Content of the HEAD file.
+    pub fn to_content(&self) -> String {
+        match self {
+            Self::Symbolic(path) => format!("{}{}", SYMBOLIC_PREFIX, path),
+            Self::Detached(hash) => hash.to_string(),
+        }
+    }
+
+    /// Reference moved by a new commit: the bookmark, or HEAD itself when detached.
+    pub fn target(&self) -> &str {
+        match self {
+            Self::Symbolic(path) => path,
+            Self::Detached(_) => HEAD,
+        }
+    }
+
+    /// Name of the local bookmark being on (`voyage`, `feature/login`).
+    pub fn bookmarker(&self) -> Option<&str> {
+        match self {
+            Self::Symbolic(path) => path.strip_prefix(LOCAL_PREFIX),
+            Self::Detached(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let hash = "c192349d0ee530038e5d925fdd701652ca755ba8";
+
+        let head = Head::parse("bookmarker: bookmarks/local/feature/login\n").unwrap();
+        assert_eq!(
+            head,
+            Head::Symbolic("bookmarks/local/feature/login".to_string())
+        );
+        assert_eq!(head.bookmarker(), Some("feature/login"));
+        assert_eq!(head.target(), "bookmarks/local/feature/login");
+        assert_eq!(Head::parse(&head.to_content()).unwrap(), head);
+
+        let head = Head::parse(hash).unwrap();
+        assert_eq!(head, Head::Detached(hash.to_string()));
+        assert_eq!(head.bookmarker(), None);
+        assert_eq!(head.target(), HEAD);
+        assert_eq!(head.to_content(), hash);
+
+        // Written by older versions
+        assert_eq!(
+            Head::parse(&format!("bookmarker: {}", hash)).unwrap(),
+            Head::Detached(hash.to_string())
+        );
+
+        assert_eq!(Head::from_value(hash), Head::Detached(hash.to_string()));
+        assert!(Head::parse("").is_err());
+        assert!(Head::parse("bookmarker: voyage").is_err());
+    }
+}
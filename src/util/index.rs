@@ -0,0 +1,387 @@
+//! Replacing the index as a whole, and the extensions kept with it.
+//!
+//! The new index is written by the core into a staging directory, and then
+//! replaces `.nss/INDEX` through its lock file, so a failure on the way (disk
+//! full, permission denied...) leaves the previous index intact.
+//!
+//! The core index format has no room for conflict stages or the cached tree,
+//! so they are written as extensions into `.nss/INDEX.ext` under the same
+//! lock, stamped with the checksum of the INDEX they belong to. Extensions
+//! whose stamp does not match INDEX (the index was written by another tool,
+//! or a crash happened between both files) are ignored.
+//!
+//! `INDEX.ext` format: the stamp line, then `<name> <length>\n<content>`
+//! for each extension.
+
+// Std
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// External
+use anyhow::{bail, Context, Result};
+use sha1::{Digest, Sha1};
+
+// Internal
+use super::lock::LockFile;
+use nss_core::repository::NssRepository;
+use nss_core::struct_set::Index;
+
+/// Conflict stages of an unmerged path (same as git index stages)
+pub const STAGE_BASE: u8 = 1;
+pub const STAGE_OURS: u8 = 2;
+pub const STAGE_THEIRS: u8 = 3;
+
+/// Extension of the conflict stages
+const UNMERGED: &str = "UNMERGED";
+
+/// Unmerged path and the blob of one stage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub stage: u8,
+    pub hash: String,
+    pub path: PathBuf,
+}
+
+/// Extensions of the index by name.
+#[derive(Debug, Default, Clone)]
+pub struct Extensions {
+    sections: BTreeMap<String, String>,
+    /// Checksum of INDEX when they were read
+    index_checksum: Option<String>,
+}
+
+impl Extensions {
+    /// Read the extensions of the current index, empty if they are stale.
+    ///
+    /// **Note:** Read them before the index, so that they are not written
+    /// for another index replaced meanwhile.
+    pub fn read(repository: &NssRepository) -> Result<Self> {
+        let nss_dir = repository.path().join(".nss");
+        let index_checksum = match fs::read(nss_dir.join("INDEX")) {
+            Ok(index) => checksum(&index),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).context("Can't read the index"),
+        };
+        let empty = Self {
+            sections: BTreeMap::new(),
+            index_checksum: Some(index_checksum.clone()),
+        };
+        let content = match fs::read(nss_dir.join("INDEX.ext")) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(empty),
+            Err(e) => return Err(e).context("Can't read the index extensions"),
+        };
+
+        let content = String::from_utf8_lossy(&content);
+        let Some((stamp, mut rest)) = content.split_once('\n') else {
+            return Ok(empty);
+        };
+        if stamp != index_checksum {
+            return Ok(empty);
+        }
+
+        let mut sections = BTreeMap::new();
+        while !rest.is_empty() {
+            let Some((header, body)) = rest.split_once('\n') else {
+                bail!("Index extensions are broken ({})", rest);
+            };
+            let (name, len) = match header.split_once(' ') {
+                Some((name, len)) => (name, len.parse::<usize>().ok()),
+                None => (header, None),
+            };
+            match len {
+                Some(len) if len <= body.len() && body.is_char_boundary(len) => {
+                    sections.insert(name.to_string(), body[..len].to_string());
+                    rest = &body[len..];
+                }
+                _ => bail!("Index extensions are broken ({})", header),
+            }
+        }
+
+        Ok(Self {
+            sections,
+            index_checksum: Some(index_checksum),
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.sections.get(name).map(|s| s.as_str())
+    }
+
+    /// Replace the extension, or remove it if the content is empty.
+    pub fn set(&mut self, name: &str, content: String) {
+        match content.is_empty() {
+            true => self.sections.remove(name),
+            false => self.sections.insert(name.to_string(), content),
+        };
+    }
+
+    /// Conflict stages of the merge in progress, sorted by path and stage.
+    pub fn conflicts(&self) -> Result<Vec<Conflict>> {
+        let mut conflicts = vec![];
+        for line in self.get(UNMERGED).unwrap_or_default().lines() {
+            let mut items = line.splitn(3, ' ');
+            match (items.next(), items.next(), items.next()) {
+                (Some(stage), Some(hash), Some(path)) => conflicts.push(Conflict {
+                    stage: stage.parse()?,
+                    hash: hash.to_string(),
+                    path: PathBuf::from(path),
+                }),
+                _ => bail!("Conflict stages are broken ({})", line),
+            }
+        }
+
+        Ok(conflicts)
+    }
+
+    pub fn set_conflicts(&mut self, conflicts: &[Conflict]) {
+        let mut conflicts = conflicts.to_vec();
+        conflicts.sort_by(|a, b| (&a.path, a.stage).cmp(&(&b.path, b.stage)));
+
+        self.set(
+            UNMERGED,
+            conflicts
+                .iter()
+                .map(|c| format!("{} {} {}\n", c.stage, c.hash, c.path.display()))
+                .collect(),
+        );
+    }
+
+    /// Drop the stages of the path (from the repository root), or of all paths.
+    pub fn resolve(&mut self, path: Option<&Path>) -> Result<()> {
+        let remains = match path {
+            Some(path) => self
+                .conflicts()?
+                .into_iter()
+                .filter(|c| c.path != path)
+                .collect::<Vec<Conflict>>(),
+            None => vec![],
+        };
+        self.set_conflicts(&remains);
+
+        Ok(())
+    }
+
+    fn to_content(&self, stamp: &str) -> String {
+        let mut content = format!("{}\n", stamp);
+        for (name, section) in &self.sections {
+            content.push_str(&format!("{} {}\n{}", name, section.len(), section));
+        }
+
+        content
+    }
+}
+
+/// Read the index, empty if it does not exist yet (before the first snap).
+///
+/// **Note:** A broken or unreadable index is an error, not taken as empty,
+/// so that it never looks like every file was deleted.
+pub fn read_index(repository: &NssRepository) -> Result<Index> {
+    match repository.path().join(".nss").join("INDEX").try_exists() {
+        Ok(false) => Ok(Index::empty()),
+        _ => repository.read_index().context("Can't read the index"),
+    }
+}
+
+/// Replace the index without extensions, keeping the previous one if writing fails.
+pub fn write_index(repository: &NssRepository, index: Index) -> Result<()> {
+    write_index_with(repository, index, &Extensions::default())
+}
+
+/// Replace the index and its extensions, keeping the previous ones if writing fails.
+pub fn write_index_with(
+    repository: &NssRepository,
+    index: Index,
+    extensions: &Extensions,
+) -> Result<()> {
+    let nss_dir = repository.path().join(".nss");
+    let mut lock = LockFile::acquire(nss_dir.join("INDEX"))?;
+    let staging = nss_dir.join("INDEX.staging");
+
+    let result = stage(&staging, index).and_then(|_| {
+        let content = fs::read(staging.join(".nss").join("INDEX"))?;
+        // Stamped with the new index, so it is ignored until INDEX is replaced
+        write_extensions_file(repository, extensions, &checksum(&content))?;
+        lock.write(&content)?;
+        lock.commit()
+    });
+    let _ = fs::remove_dir_all(&staging);
+
+    result.context("Can't write the index, so the previous index is kept")
+}
+
+/// Replace the extensions of the current index, unless the index was
+/// replaced after they were read.
+pub fn write_extensions(repository: &NssRepository, extensions: &Extensions) -> Result<()> {
+    let nss_dir = repository.path().join(".nss");
+
+    // INDEX is not changed by others while it is stamped
+    let _lock = LockFile::acquire(nss_dir.join("INDEX"))?;
+    let index = fs::read(nss_dir.join("INDEX")).context("Can't read the index")?;
+    let index_checksum = checksum(&index);
+    if extensions.index_checksum.as_ref() != Some(&index_checksum) {
+        return Ok(());
+    }
+
+    write_extensions_file(repository, extensions, &index_checksum)
+}
+
+fn write_extensions_file(
+    repository: &NssRepository,
+    extensions: &Extensions,
+    stamp: &str,
+) -> Result<()> {
+    let mut lock = LockFile::acquire(repository.path().join(".nss").join("INDEX.ext"))?;
+    lock.write(extensions.to_content(stamp).as_bytes())?;
+    lock.commit()
+}
+
+fn checksum(content: &[u8]) -> String {
+    hex::encode(Sha1::digest(content))
+}
+
+fn stage(staging: &Path, index: Index) -> Result<()> {
+    fs::create_dir_all(staging.join(".nss"))?;
+    NssRepository::new(staging.to_path_buf()).write_index(index)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::voyage;
+    use testdir::testdir;
+
+    #[test]
+    fn test_read_index() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+        let index_path = temp_dir.join(".nss").join("INDEX");
+
+        // Not snapped yet
+        let _ = fs::remove_file(&index_path);
+        assert!(read_index(&test_repo).unwrap().filemetas.is_empty());
+
+        let file_path = temp_dir.join("first.rs");
+        fs::write(&file_path, "first").unwrap();
+        let mut index = Index::empty();
+        index.add(&test_repo, &file_path, None).unwrap();
+        write_index(&test_repo, index).unwrap();
+        assert_eq!(read_index(&test_repo).unwrap().filemetas.len(), 1);
+
+        // Broken index is not taken as empty
+        fs::write(&index_path, "broken").unwrap();
+        assert!(read_index(&test_repo).is_err());
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_index() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        let file_path = temp_dir.join("first.rs");
+        fs::write(&file_path, "first").unwrap();
+        let mut index = Index::empty();
+        index.add(&test_repo, &file_path, None).unwrap();
+
+        write_index(&test_repo, index).unwrap();
+        let written = test_repo.read_index().unwrap();
+        assert_eq!(written.filemetas.len(), 1);
+        assert!(!temp_dir.join(".nss").join("INDEX.staging").exists());
+        assert!(!temp_dir.join(".nss").join("INDEX.lock").exists());
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_extensions() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        let file_path = temp_dir.join("first.rs");
+        fs::write(&file_path, "first").unwrap();
+        let mut index = Index::empty();
+        index.add(&test_repo, &file_path, None).unwrap();
+
+        let conflicts = vec![
+            Conflict {
+                stage: STAGE_THEIRS,
+                hash: "c192349d0ee530038e5d925fdd701652ca755ba8".to_string(),
+                path: PathBuf::from("first.rs"),
+            },
+            Conflict {
+                stage: STAGE_OURS,
+                hash: "5c73008ba75573c20d6a8a6e557d0556d4a84133".to_string(),
+                path: PathBuf::from("first.rs"),
+            },
+            Conflict {
+                stage: STAGE_OURS,
+                hash: "5c73008ba75573c20d6a8a6e557d0556d4a84133".to_string(),
+                path: PathBuf::from("sub dir/second.rs"),
+            },
+        ];
+        let mut extensions = Extensions::default();
+        extensions.set_conflicts(&conflicts);
+        extensions.set("TEST", "multi\nline\n".to_string());
+        write_index_with(&test_repo, index.clone(), &extensions).unwrap();
+
+        let read = Extensions::read(&test_repo).unwrap();
+        assert_eq!(read.get("TEST"), Some("multi\nline\n"));
+        let list = read.conflicts().unwrap();
+        assert_eq!(list.len(), 3);
+        assert_eq!(list[0].stage, STAGE_OURS);
+        assert_eq!(list[2].path, PathBuf::from("sub dir/second.rs"));
+
+        // Snapped path is resolved
+        let mut resolved = read.clone();
+        resolved.resolve(Some(Path::new("first.rs"))).unwrap();
+        assert_eq!(resolved.conflicts().unwrap().len(), 1);
+        resolved.resolve(None).unwrap();
+        assert!(resolved.conflicts().unwrap().is_empty());
+
+        // INDEX written without the extensions makes them stale
+        fs::write(&file_path, "changed").unwrap();
+        index.add(&test_repo, &file_path, None).unwrap();
+        test_repo.write_index(index.clone()).unwrap();
+        assert_eq!(Extensions::read(&test_repo).unwrap().get("TEST"), None);
+
+        // Read before the index was replaced, so not written
+        write_extensions(&test_repo, &read).unwrap();
+        assert_eq!(Extensions::read(&test_repo).unwrap().get("TEST"), None);
+
+        // Stamped for the current index
+        let mut current = Extensions::read(&test_repo).unwrap();
+        current.set("TEST", "current".to_string());
+        write_extensions(&test_repo, &current).unwrap();
+        assert_eq!(
+            Extensions::read(&test_repo).unwrap().get("TEST"),
+            Some("current")
+        );
+        assert!(!temp_dir.join(".nss").join("INDEX.lock").exists());
+
+        write_index(&test_repo, index).unwrap();
+        assert!(Extensions::read(&test_repo)
+            .unwrap()
+            .conflicts()
+            .unwrap()
+            .is_empty());
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+}
@@ -0,0 +1,628 @@
+//! Line-level difference between two texts.
+//!
+//! Myers algorithm is the default. Patience and histogram
+//! algorithms pick unique (or rare) lines as anchors first,
+//! which often gives more readable hunks for source code.
+
+// Std
+use std::collections::HashMap;
+use std::str::FromStr;
+
+// External
+use anyhow::{bail, Error, Result};
+
+/// Lines scanned for a NUL byte to decide a blob is binary
+const BINARY_CHECK_SIZE: usize = 8000;
+
+/// Histogram algorithm gives up on lines occurring more often than this
+const HISTOGRAM_MAX_CHAIN: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Algorithm {
+    #[default]
+    Myers,
+    Patience,
+    Histogram,
+}
+
+impl FromStr for Algorithm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "myers" => Ok(Self::Myers),
+            "patience" => Ok(Self::Patience),
+            "histogram" => Ok(Self::Histogram),
+            _ => bail!("Unknown diff algorithm: {}", s),
+        }
+    }
+}
+
+/// One step of edit script. Each value is the line index of old or new text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffOp {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Unified diff hunk (`@@ -old_start,old_len +new_start,new_len @@`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub ops: Vec<DiffOp>,
+}
+
+impl Hunk {
+    pub fn header(&self) -> String {
+        format!(
+            "@@ -{} +{} @@",
+            range(self.old_start, self.old_len),
+            range(self.new_start, self.new_len)
+        )
+    }
+}
+
+fn range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+/// Check the content in the same way as git (NUL byte in the head of content).
+pub fn is_binary(content: &[u8]) -> bool {
+    content.iter().take(BINARY_CHECK_SIZE).any(|b| *b == 0)
+}
+
+/// Split the text into lines keeping the line feed,
+/// so that a missing newline at the end of file is also a difference.
+pub fn split_lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').collect()
+}
+
+pub fn diff(old: &[&str], new: &[&str], algorithm: Algorithm) -> Vec<DiffOp> {
+    let mut ops = vec![];
+    match algorithm {
+        Algorithm::Myers => myers(old, new, 0, 0, &mut ops),
+        Algorithm::Patience => patience(old, new, 0, 0, &mut ops),
+        Algorithm::Histogram => histogram(old, new, 0, 0, &mut ops),
+    }
+
+    ops
+}
+
+/// Group the edit script into hunks surrounded by `context` equal lines.
+pub fn hunks(ops: &[DiffOp], context: usize) -> Vec<Hunk> {
+    let changes = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| !matches!(op, DiffOp::Equal(..)))
+        .map(|(i, _)| i)
+        .collect::<Vec<usize>>();
+
+    // Ranges of ops merged when context lines overlap
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for i in changes {
+        let start = i.saturating_sub(context);
+        let end = (i + context + 1).min(ops.len());
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    ranges
+        .into_iter()
+        .map(|(start, end)| {
+            // Lines before the hunk decide where the hunk starts
+            let old_start = ops[..start]
+                .iter()
+                .filter(|op| !matches!(op, DiffOp::Insert(..)))
+                .count();
+            let new_start = ops[..start]
+                .iter()
+                .filter(|op| !matches!(op, DiffOp::Delete(..)))
+                .count();
+
+            let ops = ops[start..end].to_vec();
+            let old_len = ops
+                .iter()
+                .filter(|op| !matches!(op, DiffOp::Insert(..)))
+                .count();
+            let new_len = ops
+                .iter()
+                .filter(|op| !matches!(op, DiffOp::Delete(..)))
+                .count();
+
+            Hunk {
+                old_start,
+                old_len,
+                new_start,
+                new_len,
+                ops,
+            }
+        })
+        .collect()
+}
+
+/// Render the unified diff body (without file header).
+pub fn unified(old: &[&str], new: &[&str], algorithm: Algorithm, context: usize) -> Vec<String> {
+    let ops = diff(old, new, algorithm);
+    let mut lines = vec![];
+
+    for hunk in hunks(&ops, context) {
+        lines.push(hunk.header());
+        for op in &hunk.ops {
+            let (mark, line) = match op {
+                DiffOp::Equal(o, _) => (' ', old[*o]),
+                DiffOp::Delete(o) => ('-', old[*o]),
+                DiffOp::Insert(n) => ('+', new[*n]),
+            };
+            match line.strip_suffix('\n') {
+                Some(l) => lines.push(format!("{}{}", mark, l)),
+                None => {
+                    lines.push(format!("{}{}", mark, line));
+                    lines.push("\\ No newline at end of file".to_string());
+                }
+            }
+        }
+    }
+
+    lines
+}
+
+/// Myers algorithm in linear space: the middle snake of the shortest
+/// edit script splits the texts, and each half is solved recursively,
+/// so memory is O(N + M) instead of keeping every round of V.
+fn myers(old: &[&str], new: &[&str], old_offset: usize, new_offset: usize, ops: &mut Vec<DiffOp>) {
+    let (prefix, suffix) = trim(old, new);
+    for i in 0..prefix {
+        ops.push(DiffOp::Equal(old_offset + i, new_offset + i));
+    }
+
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+    let (old_mid_offset, new_mid_offset) = (old_offset + prefix, new_offset + prefix);
+
+    if old_mid.is_empty() {
+        ops.extend((0..new_mid.len()).map(|i| DiffOp::Insert(new_mid_offset + i)));
+    } else if new_mid.is_empty() {
+        ops.extend((0..old_mid.len()).map(|i| DiffOp::Delete(old_mid_offset + i)));
+    } else {
+        let (x, y, u, v) = middle_snake(old_mid, new_mid);
+        myers(
+            &old_mid[..x],
+            &new_mid[..y],
+            old_mid_offset,
+            new_mid_offset,
+            ops,
+        );
+        for i in 0..u - x {
+            ops.push(DiffOp::Equal(
+                old_mid_offset + x + i,
+                new_mid_offset + y + i,
+            ));
+        }
+        myers(
+            &old_mid[u..],
+            &new_mid[v..],
+            old_mid_offset + u,
+            new_mid_offset + v,
+            ops,
+        );
+    }
+
+    for i in 0..suffix {
+        ops.push(DiffOp::Equal(
+            old_offset + old.len() - suffix + i,
+            new_offset + new.len() - suffix + i,
+        ));
+    }
+}
+
+/// Snake `(x, y) -> (u, v)` in the middle of the shortest edit script,
+/// found by searching forward from the head and backward from the tail
+/// until both paths overlap.
+fn middle_snake(old: &[&str], new: &[&str]) -> (usize, usize, usize, usize) {
+    let n = old.len() as isize;
+    let m = new.len() as isize;
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max = (n + m + 1) / 2;
+    let offset = max + 1;
+
+    // Furthest x on each diagonal k (index: k + offset).
+    // Backward x is counted from the tail of both texts.
+    let mut forward = vec![0isize; (2 * max + 3) as usize];
+    let mut backward = vec![0isize; (2 * max + 3) as usize];
+
+    for d in 0..=max {
+        let mut k = -d;
+        while k <= d {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && forward[idx - 1] < forward[idx + 1]) {
+                forward[idx + 1]
+            } else {
+                forward[idx - 1] + 1
+            };
+            let mut y = x - k;
+            let (start_x, start_y) = (x, y);
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[idx] = x;
+
+            // Backward diagonal of the same point, searched until d - 1
+            let back_k = delta - k;
+            if odd && back_k.abs() < d && x + backward[(back_k + offset) as usize] >= n {
+                return (start_x as usize, start_y as usize, x as usize, y as usize);
+            }
+            k += 2;
+        }
+
+        let mut k = -d;
+        while k <= d {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && backward[idx - 1] < backward[idx + 1]) {
+                backward[idx + 1]
+            } else {
+                backward[idx - 1] + 1
+            };
+            let mut y = x - k;
+            let (start_x, start_y) = (x, y);
+            while x < n && y < m && old[(n - x - 1) as usize] == new[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[idx] = x;
+
+            // Forward diagonal of the same point, searched until d
+            let forward_k = delta - k;
+            if !odd && forward_k.abs() <= d && x + forward[(forward_k + offset) as usize] >= n {
+                return (
+                    (n - x) as usize,
+                    (m - y) as usize,
+                    (n - start_x) as usize,
+                    (m - start_y) as usize,
+                );
+            }
+            k += 2;
+        }
+    }
+
+    unreachable!("Shortest edit script is at most N + M")
+}
+
+/// Equal lines at the head and the tail of both texts.
+fn trim(old: &[&str], new: &[&str]) -> (usize, usize) {
+    let prefix = old
+        .iter()
+        .zip(new.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    (prefix, suffix)
+}
+
+/// Resolve the texts separated by anchor lines (matched pairs of index).
+fn split_by_anchors(
+    old: &[&str],
+    new: &[&str],
+    old_offset: usize,
+    new_offset: usize,
+    anchors: &[(usize, usize)],
+    ops: &mut Vec<DiffOp>,
+    recurse: fn(&[&str], &[&str], usize, usize, &mut Vec<DiffOp>),
+) {
+    let (mut o, mut n) = (0, 0);
+    for &(a, b) in anchors {
+        recurse(&old[o..a], &new[n..b], old_offset + o, new_offset + n, ops);
+        ops.push(DiffOp::Equal(old_offset + a, new_offset + b));
+        o = a + 1;
+        n = b + 1;
+    }
+    recurse(&old[o..], &new[n..], old_offset + o, new_offset + n, ops);
+}
+
+fn patience(
+    old: &[&str],
+    new: &[&str],
+    old_offset: usize,
+    new_offset: usize,
+    ops: &mut Vec<DiffOp>,
+) {
+    let (prefix, suffix) = trim(old, new);
+    for i in 0..prefix {
+        ops.push(DiffOp::Equal(old_offset + i, new_offset + i));
+    }
+
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+    let (old_mid_offset, new_mid_offset) = (old_offset + prefix, new_offset + prefix);
+
+    // Lines appearing exactly once in both texts
+    let mut counts: HashMap<&str, (usize, usize, usize, usize)> = HashMap::new();
+    for (i, line) in old_mid.iter().enumerate() {
+        let entry = counts.entry(line).or_insert((0, 0, 0, 0));
+        entry.0 += 1;
+        entry.1 = i;
+    }
+    for (i, line) in new_mid.iter().enumerate() {
+        let entry = counts.entry(line).or_insert((0, 0, 0, 0));
+        entry.2 += 1;
+        entry.3 = i;
+    }
+    let mut uniques = counts
+        .values()
+        .filter(|(old_count, _, new_count, _)| *old_count == 1 && *new_count == 1)
+        .map(|(_, a, _, b)| (*a, *b))
+        .collect::<Vec<(usize, usize)>>();
+    uniques.sort();
+
+    let anchors = longest_increasing(&uniques);
+    if anchors.is_empty() {
+        myers(old_mid, new_mid, old_mid_offset, new_mid_offset, ops);
+    } else {
+        split_by_anchors(
+            old_mid,
+            new_mid,
+            old_mid_offset,
+            new_mid_offset,
+            &anchors,
+            ops,
+            patience,
+        );
+    }
+
+    for i in 0..suffix {
+        ops.push(DiffOp::Equal(
+            old_offset + old.len() - suffix + i,
+            new_offset + new.len() - suffix + i,
+        ));
+    }
+}
+
+/// Longest chain of pairs (sorted by old index) whose new index is increasing.
+fn longest_increasing(pairs: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    // tails[i]: index of the pair ending the best chain of length i + 1
+    let mut tails: Vec<usize> = vec![];
+    let mut prev: Vec<Option<usize>> = vec![None; pairs.len()];
+
+    for (i, pair) in pairs.iter().enumerate() {
+        let pos = tails.partition_point(|&t| pairs[t].1 < pair.1);
+        if pos > 0 {
+            prev[i] = Some(tails[pos - 1]);
+        }
+        if pos == tails.len() {
+            tails.push(i);
+        } else {
+            tails[pos] = i;
+        }
+    }
+
+    let mut chain = vec![];
+    let mut cursor = tails.last().copied();
+    while let Some(i) = cursor {
+        chain.push(pairs[i]);
+        cursor = prev[i];
+    }
+    chain.reverse();
+
+    chain
+}
+
+fn histogram(
+    old: &[&str],
+    new: &[&str],
+    old_offset: usize,
+    new_offset: usize,
+    ops: &mut Vec<DiffOp>,
+) {
+    let (prefix, suffix) = trim(old, new);
+    for i in 0..prefix {
+        ops.push(DiffOp::Equal(old_offset + i, new_offset + i));
+    }
+
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+    let (old_mid_offset, new_mid_offset) = (old_offset + prefix, new_offset + prefix);
+
+    // Occurrences of each line in old text
+    let mut occurrences: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, line) in old_mid.iter().enumerate() {
+        occurrences.entry(line).or_default().push(i);
+    }
+
+    // Longest common region started from the rarest line
+    let mut best: Option<(usize, usize, usize, usize)> = None; // (count, old, new, len)
+    for (b, line) in new_mid.iter().enumerate() {
+        let Some(indexes) = occurrences.get(line) else {
+            continue;
+        };
+        if indexes.len() > HISTOGRAM_MAX_CHAIN {
+            continue;
+        }
+        for &a in indexes {
+            let len = old_mid[a..]
+                .iter()
+                .zip(new_mid[b..].iter())
+                .take_while(|(x, y)| x == y)
+                .count();
+            let better = match best {
+                None => true,
+                Some((count, _, _, best_len)) => {
+                    indexes.len() < count || (indexes.len() == count && len > best_len)
+                }
+            };
+            if better {
+                best = Some((indexes.len(), a, b, len));
+            }
+        }
+    }
+
+    match best {
+        None => myers(old_mid, new_mid, old_mid_offset, new_mid_offset, ops),
+        Some((_, a, b, len)) => {
+            histogram(
+                &old_mid[..a],
+                &new_mid[..b],
+                old_mid_offset,
+                new_mid_offset,
+                ops,
+            );
+            for i in 0..len {
+                ops.push(DiffOp::Equal(
+                    old_mid_offset + a + i,
+                    new_mid_offset + b + i,
+                ));
+            }
+            histogram(
+                &old_mid[a + len..],
+                &new_mid[b + len..],
+                old_mid_offset + a + len,
+                new_mid_offset + b + len,
+                ops,
+            );
+        }
+    }
+
+    for i in 0..suffix {
+        ops.push(DiffOp::Equal(
+            old_offset + old.len() - suffix + i,
+            new_offset + new.len() - suffix + i,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Apply the edit script to old text and check the result is new text.
+    fn apply(old: &[&str], new: &[&str], ops: &[DiffOp]) -> Vec<String> {
+        let mut result = vec![];
+        for op in ops {
+            match op {
+                DiffOp::Equal(o, n) => {
+                    assert_eq!(old[*o], new[*n]);
+                    result.push(old[*o].to_string())
+                }
+                DiffOp::Insert(n) => result.push(new[*n].to_string()),
+                DiffOp::Delete(_) => (),
+            }
+        }
+
+        result
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = split_lines("a\nb\nc\na\nb\nb\na\n");
+        let new = split_lines("c\nb\na\nb\na\nc\n");
+
+        for algorithm in [Algorithm::Myers, Algorithm::Patience, Algorithm::Histogram] {
+            let ops = diff(&old, &new, algorithm);
+            assert_eq!(apply(&old, &new, &ops), new);
+
+            let deleted = ops
+                .iter()
+                .filter(|op| matches!(op, DiffOp::Delete(_)))
+                .count();
+            let inserted = ops
+                .iter()
+                .filter(|op| matches!(op, DiffOp::Insert(_)))
+                .count();
+            assert_eq!(old.len() - deleted, new.len() - inserted);
+        }
+
+        // Shortest edit script
+        let ops = diff(&old, &new, Algorithm::Myers);
+        assert_eq!(
+            ops.iter()
+                .filter(|op| !matches!(op, DiffOp::Equal(..)))
+                .count(),
+            5
+        );
+
+        // Every line differs
+        let old = (0..2000)
+            .map(|i| format!("old {}\n", i))
+            .collect::<Vec<_>>();
+        let new = (0..2000)
+            .map(|i| format!("new {}\n", i))
+            .collect::<Vec<_>>();
+        let old = old.iter().map(|l| l.as_str()).collect::<Vec<_>>();
+        let new = new.iter().map(|l| l.as_str()).collect::<Vec<_>>();
+        let ops = diff(&old, &new, Algorithm::Myers);
+        assert_eq!(apply(&old, &new, &ops), new);
+        assert_eq!(ops.len(), 4000);
+
+        // Empty text
+        assert!(diff(&[], &[], Algorithm::Myers).is_empty());
+        assert_eq!(
+            diff(&[], &["a\n"], Algorithm::Patience),
+            vec![DiffOp::Insert(0)]
+        );
+    }
+
+    #[test]
+    fn test_unified() {
+        let old = split_lines("1\n2\n3\n4\n5\n6\n7\n8\n9\n");
+        let new = split_lines("1\n2\n3\n4\nfive\n6\n7\n8\n9");
+
+        let lines = unified(&old, &new, Algorithm::Myers, 3);
+        assert_eq!(
+            lines,
+            vec![
+                "@@ -2,8 +2,8 @@",
+                " 2",
+                " 3",
+                " 4",
+                "-5",
+                "+five",
+                " 6",
+                " 7",
+                " 8",
+                "-9",
+                "+9",
+                "\\ No newline at end of file",
+            ]
+        );
+
+        let lines = unified(&old, &new, Algorithm::Myers, 0);
+        assert_eq!(lines[0], "@@ -5 +5 @@");
+        assert_eq!(lines[3], "@@ -9 +9 @@");
+
+        // New file
+        let lines = unified(&[], &split_lines("a\n"), Algorithm::Myers, 3);
+        assert_eq!(lines, vec!["@@ -0,0 +1 @@", "+a"]);
+    }
+
+    #[test]
+    fn test_is_binary() {
+        assert!(!is_binary(b"fn main() {}\n"));
+        assert!(is_binary(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"));
+    }
+
+    #[test]
+    fn test_algorithm_from_str() {
+        assert_eq!("myers".parse::<Algorithm>().unwrap(), Algorithm::Myers);
+        assert_eq!(
+            "histogram".parse::<Algorithm>().unwrap(),
+            Algorithm::Histogram
+        );
+        assert!("fast".parse::<Algorithm>().is_err());
+    }
+}
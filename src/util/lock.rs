@@ -0,0 +1,141 @@
+//! Lock-file protocol for HEAD, bookmarks and INDEX.
+//!
+//! 1. `<name>.lock` is created exclusively, so only one process can hold it
+//! 2. The new content is written into the lock file and synced to the disk
+//! 3. The lock file is renamed over `<name>`
+//!
+//! A crash on the way leaves `<name>` as it was. The lock file is removed
+//! if the update is given up (dropped without [`LockFile::commit`]).
+
+// Std
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// External
+use anyhow::{bail, Context, Result};
+
+#[derive(Debug)]
+pub struct LockFile {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: Option<File>,
+    /// Renamed over the locked file, so nothing to remove
+    committed: bool,
+}
+
+impl LockFile {
+    /// Take the lock of the file, fails if another process holds it.
+    pub fn acquire<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut lock_path = path.clone().into_os_string();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+
+        let file = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => bail!(
+                "Unable to lock {}: another nssi process seems to be running.\n\
+                 If no process is running, remove {} and try again.",
+                path.display(),
+                lock_path.display()
+            ),
+            Err(e) => return Err(e).with_context(|| format!("Unable to lock {}", path.display())),
+        };
+
+        Ok(Self {
+            path,
+            lock_path,
+            file: Some(file),
+            committed: false,
+        })
+    }
+
+    /// Path of the locked file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn write(&mut self, content: &[u8]) -> Result<()> {
+        self.file.as_mut().unwrap().write_all(content)?;
+
+        Ok(())
+    }
+
+    /// Replace the locked file with the written content and release the lock.
+    pub fn commit(mut self) -> Result<()> {
+        let file = self.file.take().unwrap();
+        file.sync_all()?;
+        drop(file);
+
+        fs::rename(&self.lock_path, &self.path)
+            .with_context(|| format!("Can't update {}", self.path.display()))?;
+        self.committed = true;
+
+        // Make the rename itself durable
+        if let Some(parent) = self.path.parent() {
+            File::open(parent)?.sync_all()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        // Not committed or failed to commit, so the lock file is still ours
+        if !self.committed {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testdir::testdir;
+
+    #[test]
+    fn test_lock_file() {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let path = temp_dir.join("voyage");
+        let lock_path = temp_dir.join("voyage.lock");
+        fs::write(&path, "old").unwrap();
+
+        // Held lock blocks other updates
+        let mut lock = LockFile::acquire(&path).unwrap();
+        assert!(lock_path.exists());
+        assert!(LockFile::acquire(&path).is_err());
+
+        lock.write(b"new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        lock.commit().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(!lock_path.exists());
+
+        // Given up update keeps the file
+        let mut lock = LockFile::acquire(&path).unwrap();
+        lock.write(b"broken").unwrap();
+        drop(lock);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(!lock_path.exists());
+
+        // Committed lock does not remove the lock taken after it
+        let lock = LockFile::acquire(&path).unwrap();
+        lock.commit().unwrap();
+        let next = LockFile::acquire(&path).unwrap();
+        assert!(lock_path.exists());
+        drop(next);
+        assert!(!lock_path.exists());
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+}
@@ -0,0 +1,182 @@
+//! Writing objects into the object database.
+//!
+//! Blobs are hashed, compressed and restored through fixed-size buffers,
+//! so memory use does not depend on the file size.
+
+// Std
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// External
+use anyhow::{bail, Context, Result};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use sha1::{Digest, Sha1};
+
+// Internal
+use nss_core::repository::NssRepository;
+use nss_core::struct_set::Object;
+
+/// Size of the buffer the file contents stream through
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Sequence number of the temporary object files of this process
+static TEMP_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Hash the file as blob without loading it into memory.
+pub fn hash_blob(file_path: &Path) -> Result<Vec<u8>> {
+    stream_blob(file_path, |_| Ok(()))
+}
+
+/// Hash the file as blob and write it unless the same object already exists.
+///
+/// **Note:** Safe to call from several threads at once.
+pub fn store_blob(repository: &NssRepository, file_path: &Path) -> Result<Vec<u8>> {
+    let hash = hash_blob(file_path)?;
+    let object_path = repository.objects_path(hex::encode(&hash));
+    if object_path.exists() {
+        return Ok(hash);
+    }
+
+    // Fan-out directory may be created by another worker at the same time
+    let object_dir = object_path.parent().unwrap();
+    fs::create_dir_all(object_dir)?;
+
+    // Compress into a temporary file, so that no broken object is left
+    let temp_path = object_dir.join(format!(
+        "tmp_{}_{}",
+        process::id(),
+        TEMP_COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = compress_blob(file_path, &temp_path).and_then(|hash| {
+        // Named by the hash of what was compressed, the file may be changed meanwhile
+        fs::rename(&temp_path, repository.objects_path(hex::encode(&hash)))?;
+        Ok(hash)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result.with_context(|| format!("Can't write object of {}", file_path.display()))
+}
+
+/// Content of the blob.
+pub fn read_blob(repository: &NssRepository, hash: &str) -> Result<Vec<u8>> {
+    match repository.read_object(hash)? {
+        Object::Blob(blob) => Ok(blob.content),
+        _ => bail!("{} is not blob hash", hash),
+    }
+}
+
+/// Write the content of the blob into the file without loading it into memory.
+pub fn restore_blob(repository: &NssRepository, hash: &str, file_path: &Path) -> Result<()> {
+    let object = File::open(repository.objects_path(hash))
+        .with_context(|| format!("Not found object {}", hash))?;
+    let mut reader = BufReader::with_capacity(BUFFER_SIZE, ZlibDecoder::new(object));
+
+    let mut header = vec![];
+    reader.read_until(b'\0', &mut header)?;
+    let size = match String::from_utf8_lossy(&header)
+        .trim_end_matches('\0')
+        .split_once(' ')
+    {
+        Some(("blob", size)) => size
+            .parse::<u64>()
+            .with_context(|| format!("Object {} is broken", hash))?,
+        Some(_) => bail!("{} is not blob hash", hash),
+        None => bail!("Object {} is broken", hash),
+    };
+
+    let mut file = File::create(file_path)?;
+    if io::copy(&mut reader.take(size), &mut file)? != size {
+        bail!("Object {} is broken", hash)
+    }
+
+    Ok(())
+}
+
+fn compress_blob(file_path: &Path, temp_path: &Path) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(File::create(temp_path)?, Compression::default());
+    let hash = stream_blob(file_path, |chunk| Ok(encoder.write_all(chunk)?))?;
+    encoder.finish()?.sync_all()?;
+
+    Ok(hash)
+}
+
+/// Read the file as blob chunk by chunk, and return its hash.
+fn stream_blob<F>(file_path: &Path, mut f: F) -> Result<Vec<u8>>
+where
+    F: FnMut(&[u8]) -> Result<()>,
+{
+    let file = File::open(file_path)?;
+    let size = file.metadata()?.len();
+
+    let header = format!("blob {}\0", size);
+    let mut hasher = Sha1::new();
+    hasher.update(header.as_bytes());
+    f(header.as_bytes())?;
+
+    // Only the size in the header is read, even if the file grows meanwhile
+    let mut content = file.take(size);
+    let mut buffer = vec![0; BUFFER_SIZE];
+    let mut read = 0;
+    loop {
+        let n = content.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+        f(&buffer[..n])?;
+        read += n as u64;
+    }
+    if read != size {
+        bail!("{} was changed while reading", file_path.display())
+    }
+
+    Ok(hasher.finalize().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::voyage;
+    use nss_core::struct_set::{Blob, Hashable, Object};
+    use testdir::testdir;
+
+    #[test]
+    fn test_store_blob() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        // Larger than the buffer
+        let file_path = temp_dir.join("asset.bin");
+        let content = (0..BUFFER_SIZE * 3 + 7)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<u8>>();
+        fs::write(&file_path, &content).unwrap();
+
+        let hash = store_blob(&test_repo, &file_path).unwrap();
+        assert_eq!(hash, Blob::new(&file_path).unwrap().to_hash());
+        assert_eq!(hash_blob(&file_path).unwrap(), hash);
+
+        // Readable as an usual object
+        match test_repo.read_object(hex::encode(&hash)).unwrap() {
+            Object::Blob(blob) => assert_eq!(blob.content, content),
+            _ => panic!("not blob"),
+        }
+
+        let restored = temp_dir.join("restored.bin");
+        restore_blob(&test_repo, &hex::encode(&hash), &restored).unwrap();
+        assert_eq!(fs::read(&restored).unwrap(), content);
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+}
@@ -0,0 +1,119 @@
+//! Parents of a commit.
+//!
+//! The commit object keeps its parents in the single `parent` field, so they
+//! are encoded into it: hashes in order separated by a space, and `None` for
+//! the root commit (as written by the first versions of reg).
+
+// External
+use anyhow::{bail, Result};
+
+// Internal
+use nss_core::struct_set::Commit;
+
+/// Field value of the root commit
+const ROOT: &str = "None";
+
+/// Parent hashes of a commit, the first parent first (empty for the root commit).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Parents(Vec<String>);
+
+impl Parents {
+    pub fn new(hashes: Vec<String>) -> Self {
+        Self(hashes)
+    }
+
+    /// Parents of the commit object.
+    pub fn of(commit: &Commit) -> Result<Self> {
+        Self::decode(&commit.parent)
+    }
+
+    /// Parse the `parent` field of the commit object.
+    pub fn decode(field: &str) -> Result<Self> {
+        if field.trim() == ROOT {
+            return Ok(Self::default());
+        }
+
+        let mut hashes = vec![];
+        for hash in field.split_whitespace() {
+            if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                bail!("Broken parent of the commit ({})", field);
+            }
+            hashes.push(hash.to_string());
+        }
+        if hashes.is_empty() {
+            bail!("Broken parent of the commit (empty)");
+        }
+
+        Ok(Self(hashes))
+    }
+
+    /// Value of the `parent` field of the commit object.
+    pub fn encode(&self) -> String {
+        match self.0.is_empty() {
+            true => ROOT.to_string(),
+            false => self.0.join(" "),
+        }
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn first(&self) -> Option<&String> {
+        self.0.first()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&String> {
+        self.0.get(index)
+    }
+
+    pub fn into_vec(self) -> Vec<String> {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIRST: &str = "c192349d0ee530038e5d925fdd701652ca755ba8";
+    const SECOND: &str = "5c73008ba75573c20d6a8a6e557d0556d4a84133";
+
+    #[test]
+    fn test_root() {
+        let parents = Parents::default();
+        assert!(parents.is_root());
+        assert_eq!(parents.encode(), "None");
+        assert_eq!(Parents::decode("None").unwrap(), parents);
+        assert_eq!(parents.first(), None);
+    }
+
+    #[test]
+    fn test_single_parent() {
+        let parents = Parents::new(vec![FIRST.to_string()]);
+        assert_eq!(parents.encode(), FIRST);
+        assert_eq!(Parents::decode(FIRST).unwrap(), parents);
+        assert_eq!(parents.first().unwrap(), FIRST);
+        assert!(!parents.is_root());
+    }
+
+    #[test]
+    fn test_merge_parents() {
+        let parents = Parents::new(vec![FIRST.to_string(), SECOND.to_string()]);
+        let field = parents.encode();
+        assert_eq!(field, format!("{} {}", FIRST, SECOND));
+
+        let decoded = Parents::decode(&field).unwrap();
+        assert_eq!(decoded, parents);
+        assert_eq!(decoded.first().unwrap(), FIRST);
+        assert_eq!(decoded.get(1).unwrap(), SECOND);
+        assert_eq!(decoded.into_vec(), vec![FIRST, SECOND]);
+    }
+
+    #[test]
+    fn test_decode_broken() {
+        assert!(Parents::decode("").is_err());
+        assert!(Parents::decode("main").is_err());
+        assert!(Parents::decode(&format!("{} None", FIRST)).is_err());
+    }
+}
@@ -0,0 +1,66 @@
+//! Worker pool for CPU-bound work on many items (hashing and compressing objects).
+
+// Std
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// Apply the function to every item on all CPU cores.
+///
+/// **Note:** Results are in the same order as the items regardless of scheduling.
+pub fn parallel_map<T, R, F>(items: &[T], f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let workers = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(items.len());
+    if workers <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let (f, next) = (&f, &next);
+    let mut results = thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| {
+                scope.spawn(move || {
+                    let mut done = vec![];
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(i) else {
+                            break;
+                        };
+                        done.push((i, f(item)));
+                    }
+                    done
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().expect("Worker thread panicked"))
+            .collect::<Vec<(usize, R)>>()
+    });
+    results.sort_by_key(|(i, _)| *i);
+
+    results.into_iter().map(|(_, r)| r).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parallel_map() {
+        let items = (0..1000).collect::<Vec<usize>>();
+        let results = parallel_map(&items, |i| i * 2);
+        assert_eq!(results, items.iter().map(|i| i * 2).collect::<Vec<usize>>());
+
+        assert!(parallel_map(&Vec::<usize>::new(), |i| *i).is_empty());
+        assert_eq!(parallel_map(&[1], |i| i + 1), vec![2]);
+    }
+}
@@ -0,0 +1,250 @@
+//! Commit formatting with placeholders (like `git log --format`).
+//!
+//! | Placeholder | Meaning |
+//! | --- | --- |
+//! | `%H` / `%h` | commit hash / abbreviated commit hash |
+//! | `%T` | tree hash |
+//! | `%P` | parent hashes |
+//! | `%an` / `%ae` / `%ad` | author name / email / date |
+//! | `%cn` / `%ce` | committer name / email |
+//! | `%s` / `%b` | subject (first line) / body of the message |
+//! | `%d` | bookmarks pointing to the commit |
+//! | `%n` / `%%` | line feed / `%` |
+
+// External
+use anyhow::{bail, Result};
+
+// Internal
+use super::date::format_local;
+use super::refs::Decoration;
+use super::walk::CommitNode;
+
+/// Length of the abbreviated hash
+pub const ABBREV_LEN: usize = 7;
+
+const ONELINE: &str = "%h%d %s";
+const FULL: &str = "Commit: %H%d%nAuthor: %an <%ae>%nCommitter: %cn <%ce>%n%n    %s%n%b";
+/// Rest of `raw` after the parent lines
+const RAW_PERSON: &str = "author %an <%ae>%ncommitter %cn <%ce>%n%n    %s%n%b";
+
+/// Template of the named preset, `None` if the name is not preset.
+///
+/// **Note:** `medium` is the default layout of story, and `raw` has
+/// one line for each parent, so they have no template (see [`raw`]).
+pub fn preset(name: &str) -> Option<&'static str> {
+    match name {
+        "oneline" => Some(ONELINE),
+        "full" => Some(FULL),
+        _ => None,
+    }
+}
+
+/// Layout of the commit object, with a `parent` line for each parent
+/// (none for the root commit).
+pub fn raw(node: &CommitNode) -> String {
+    let mut output = format!("commit {}\ntree {}\n", node.hash, node.commit.tree_hash);
+    for parent in &node.parents {
+        output.push_str(&format!("parent {}\n", parent));
+    }
+    output.push_str(&expand(RAW_PERSON, node, None));
+
+    output
+}
+
+/// Check the template before formatting any commit.
+pub fn validate(template: &str) -> Result<()> {
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            continue;
+        }
+        match chars.next() {
+            Some('H' | 'h' | 'T' | 'P' | 's' | 'b' | 'd' | 'n' | '%') => {}
+            Some(kind @ ('a' | 'c')) => match chars.next() {
+                Some('n' | 'e') => {}
+                Some('d') if kind == 'a' => {}
+                other => bail!(
+                    "Unknown placeholder: %{}{}",
+                    kind,
+                    other.map(String::from).unwrap_or_default()
+                ),
+            },
+            other => bail!(
+                "Unknown placeholder: %{}",
+                other.map(String::from).unwrap_or_default()
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+/// Expand the placeholders in the template.
+///
+/// **Note:** The template must be checked by [`validate`].
+pub fn expand(template: &str, node: &CommitNode, labels: Option<&Vec<Decoration>>) -> String {
+    let commit = &node.commit;
+    let (subject, body) = split_message(&commit.message);
+
+    let mut output = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('H') => output.push_str(&node.hash),
+            Some('h') => output.push_str(&node.hash[0..ABBREV_LEN]),
+            Some('T') => output.push_str(&commit.tree_hash),
+            Some('P') => output.push_str(&node.parents.join(" ")),
+            Some('a') => match chars.next() {
+                Some('n') => output.push_str(split_person(&commit.author).0),
+                Some('e') => output.push_str(split_person(&commit.author).1),
+                _ => output.push_str(&format_local(commit.date.timestamp())),
+            },
+            Some('c') => match chars.next() {
+                Some('n') => output.push_str(split_person(&commit.committer).0),
+                _ => output.push_str(split_person(&commit.committer).1),
+            },
+            Some('s') => output.push_str(subject),
+            Some('b') => output.push_str(body),
+            Some('d') => output.push_str(&decoration(labels)),
+            Some('n') => output.push('\n'),
+            _ => output.push('%'),
+        }
+    }
+
+    output
+}
+
+/// Name and email written as `<name>\0 <email>`.
+fn split_person(person: &str) -> (&str, &str) {
+    match person.split_once('\0') {
+        Some((name, email)) => (name.trim(), email.trim()),
+        None => (person.trim(), ""),
+    }
+}
+
+/// Split the message into the first line and the rest.
+fn split_message(message: &str) -> (&str, &str) {
+    match message.split_once('\n') {
+        Some((subject, body)) => (subject.trim_end(), body.trim_start_matches('\n')),
+        None => (message.trim_end(), ""),
+    }
+}
+
+/// Plain decoration text (` (HEAD -> voyage, origin/voyage)`).
+fn decoration(labels: Option<&Vec<Decoration>>) -> String {
+    let labels = match labels {
+        Some(labels) if !labels.is_empty() => labels,
+        _ => return String::new(),
+    };
+
+    let labels = labels
+        .iter()
+        .map(|label| match label {
+            Decoration::Head => "HEAD".to_string(),
+            Decoration::HeadTo(name) => format!("HEAD -> {}", name),
+            Decoration::Local(name) | Decoration::Remote(name) => name.to_string(),
+        })
+        .collect::<Vec<String>>();
+
+    format!(" ({})", labels.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::parents::Parents;
+    use nss_core::struct_set::Commit;
+
+    fn node() -> CommitNode {
+        let commit = Commit::new(
+            "c192349d0ee530038e5d925fdd701652ca755ba8".to_string(),
+            Parents::default().encode(),
+            "noshishi\0 noshishi@nss.com".to_string(),
+            "nopeNoshishi\0 ".to_string(),
+            "Fix story\n\nFilter commits by date".to_string(),
+        )
+        .unwrap();
+
+        CommitNode {
+            hash: "2d0a0c0b4fbbba2d4f6e5e9a3f2e6a8a8f0a9f33".to_string(),
+            commit,
+            parents: vec![],
+        }
+    }
+
+    #[test]
+    fn test_expand() {
+        let node = node();
+        let labels = vec![
+            Decoration::HeadTo("voyage".to_string()),
+            Decoration::Remote("origin/voyage".to_string()),
+        ];
+
+        assert_eq!(
+            expand("%h%d %s", &node, Some(&labels)),
+            "2d0a0c0 (HEAD -> voyage, origin/voyage) Fix story"
+        );
+        assert_eq!(
+            expand("%H %T [%P]", &node, None),
+            "2d0a0c0b4fbbba2d4f6e5e9a3f2e6a8a8f0a9f33 c192349d0ee530038e5d925fdd701652ca755ba8 []"
+        );
+        assert_eq!(
+            expand("%an <%ae>%n%cn <%ce>", &node, None),
+            "noshishi <noshishi@nss.com>\nnopeNoshishi <>"
+        );
+        assert_eq!(
+            expand("%b|100%%", &node, None),
+            "Filter commits by date|100%"
+        );
+        assert!(!expand("%ad", &node, None).is_empty());
+    }
+
+    #[test]
+    fn test_raw() {
+        let mut node = node();
+        assert_eq!(
+            raw(&node),
+            "commit 2d0a0c0b4fbbba2d4f6e5e9a3f2e6a8a8f0a9f33\n\
+             tree c192349d0ee530038e5d925fdd701652ca755ba8\n\
+             author noshishi <noshishi@nss.com>\n\
+             committer nopeNoshishi <>\n\n    \
+             Fix story\n\
+             Filter commits by date"
+        );
+
+        node.parents = vec![
+            "5c73008ba75573c20d6a8a6e557d0556d4a84133".to_string(),
+            "c192349d0ee530038e5d925fdd701652ca755ba8".to_string(),
+        ];
+        let lines = raw(&node)
+            .lines()
+            .map(String::from)
+            .collect::<Vec<String>>();
+        assert_eq!(lines[2], "parent 5c73008ba75573c20d6a8a6e557d0556d4a84133");
+        assert_eq!(lines[3], "parent c192349d0ee530038e5d925fdd701652ca755ba8");
+        assert!(lines[4].starts_with("author "));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(RAW_PERSON).is_ok());
+        assert!(validate(FULL).is_ok());
+        assert!(validate("%H %an %ad %cn %ce %d%n").is_ok());
+        assert!(validate("%x").is_err());
+        assert!(validate("%cd").is_err());
+        assert!(validate("%a").is_err());
+        assert!(validate("100%").is_err());
+    }
+
+    #[test]
+    fn test_preset() {
+        assert_eq!(preset("oneline"), Some(ONELINE));
+        assert_eq!(preset("medium"), None);
+        assert_eq!(preset("raw"), None);
+        assert_eq!(preset("%H"), None);
+    }
+}
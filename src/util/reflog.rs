@@ -0,0 +1,245 @@
+//! Reflog: history of the values of HEAD and each bookmark.
+//!
+//! Every update through [`RefTransaction`](super::refs::RefTransaction) is
+//! appended to `.nss/logs/<reference>` (e.g. `logs/HEAD`,
+//! `logs/bookmarks/local/voyage`), one update per line:
+//!
+//! `<old hash> <new hash> <name> <<email>> <timestamp> <timezone>\t<reason>`
+//!
+//! **Note:** The old hash of a reference which had no commit is all zero.
+
+// Std
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+// External
+use anyhow::{bail, Context, Result};
+use chrono::Local;
+
+// Internal
+use super::date::parse_timestamp;
+use super::refs::prune_dirs;
+use nss_core::repository::NssRepository;
+
+/// Old hash of a reference which had no commit
+const NULL_HASH: &str = "0000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReflogEntry {
+    /// `None` if the reference had no commit
+    pub old: Option<String>,
+    pub new: String,
+    /// `<name> <<email>>`
+    pub identity: String,
+    pub timestamp: i64,
+    /// Offset like `+0900`
+    pub timezone: String,
+    /// What moved the reference (e.g. `reg: Fix story`)
+    pub reason: String,
+}
+
+impl ReflogEntry {
+    /// Entry of the update made now by the user in the config.
+    pub fn new(
+        repository: &NssRepository,
+        old: Option<String>,
+        new: &str,
+        reason: &str,
+    ) -> Result<Self> {
+        let config = repository.read_config()?;
+        let now = Local::now();
+
+        Ok(Self {
+            old,
+            new: new.to_string(),
+            identity: format!(
+                "{} <{}>",
+                config.username(),
+                config.useremail().unwrap_or_default()
+            ),
+            timestamp: now.timestamp(),
+            timezone: now.format("%z").to_string(),
+            reason: reason.lines().next().unwrap_or_default().to_string(),
+        })
+    }
+
+    fn to_line(&self) -> String {
+        format!(
+            "{} {} {} {} {}\t{}\n",
+            self.old.as_deref().unwrap_or(NULL_HASH),
+            self.new,
+            self.identity,
+            self.timestamp,
+            self.timezone,
+            self.reason
+        )
+    }
+
+    fn parse(line: &str) -> Result<Self> {
+        let (head, reason) = line.split_once('\t').unwrap_or((line, ""));
+        let mut items = head.splitn(3, ' ');
+        let (Some(old), Some(new), Some(rest)) = (items.next(), items.next(), items.next()) else {
+            bail!("Reflog is broken ({})", line);
+        };
+        let mut items = rest.rsplitn(3, ' ');
+        let (Some(timezone), Some(timestamp), Some(identity)) =
+            (items.next(), items.next(), items.next())
+        else {
+            bail!("Reflog is broken ({})", line);
+        };
+
+        Ok(Self {
+            old: match old {
+                NULL_HASH => None,
+                h => Some(h.to_string()),
+            },
+            new: new.to_string(),
+            identity: identity.to_string(),
+            timestamp: timestamp
+                .parse::<i64>()
+                .with_context(|| format!("Reflog is broken ({})", line))?,
+            timezone: timezone.to_string(),
+            reason: reason.to_string(),
+        })
+    }
+}
+
+fn log_path(repository: &NssRepository, reference: &str) -> PathBuf {
+    repository.path().join(".nss").join("logs").join(reference)
+}
+
+/// Append the entry to the log of the reference (`HEAD` or path from `.nss`).
+pub fn append(repository: &NssRepository, reference: &str, entry: &ReflogEntry) -> Result<()> {
+    let path = log_path(repository, reference);
+    fs::create_dir_all(path.parent().unwrap())?;
+
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    file.write_all(entry.to_line().as_bytes())
+        .with_context(|| format!("Can't write reflog of {}", reference))?;
+
+    Ok(())
+}
+
+/// Remove the log of the deleted reference.
+pub fn remove(repository: &NssRepository, reference: &str) -> Result<()> {
+    let path = log_path(repository, reference);
+    if path.exists() {
+        fs::remove_file(&path)?;
+        prune_dirs(&path, &repository.path().join(".nss").join("logs"))?;
+    }
+
+    Ok(())
+}
+
+/// Move the log of the renamed reference, before the entries already logged to the new one.
+pub fn rename(repository: &NssRepository, from: &str, to: &str) -> Result<()> {
+    let from_path = log_path(repository, from);
+    if !from_path.exists() {
+        return Ok(());
+    }
+
+    let to_path = log_path(repository, to);
+    let mut content = fs::read_to_string(&from_path)?;
+    if to_path.exists() {
+        content.push_str(&fs::read_to_string(&to_path)?);
+    }
+    fs::create_dir_all(to_path.parent().unwrap())?;
+    fs::write(&to_path, content)?;
+
+    remove(repository, from)
+}
+
+/// Entries of the log, newest first (empty if the reference was never updated).
+pub fn read(repository: &NssRepository, reference: &str) -> Result<Vec<ReflogEntry>> {
+    let content = match fs::read_to_string(log_path(repository, reference)) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+
+    content.lines().rev().map(ReflogEntry::parse).collect()
+}
+
+/// Value of the reference at `@{<selector>}`: `N` updates before now,
+/// or at the date (see [`super::date`]).
+pub fn lookup(repository: &NssRepository, reference: &str, selector: &str) -> Result<String> {
+    let entries = read(repository, reference)?;
+    if entries.is_empty() {
+        bail!("{} has no reflog", reference);
+    }
+
+    if let Ok(n) = selector.parse::<usize>() {
+        return match entries.get(n) {
+            Some(entry) => Ok(entry.new.clone()),
+            None => bail!(
+                "Reflog of {} has only {} entries ({}@{{{}}})",
+                reference,
+                entries.len(),
+                reference,
+                n
+            ),
+        };
+    }
+
+    let timestamp = parse_timestamp(selector)?;
+    match entries.iter().find(|e| e.timestamp <= timestamp) {
+        Some(entry) => Ok(entry.new.clone()),
+        // Older than the log, the value before the first update
+        None => match &entries.last().unwrap().old {
+            Some(old) => Ok(old.clone()),
+            None => bail!("{} had no commit at {}", reference, selector),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::voyage;
+    use testdir::testdir;
+
+    #[test]
+    fn test_reflog() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        let first = "1".repeat(40);
+        let second = "2".repeat(40);
+        let voyage = "bookmarks/local/voyage";
+        assert!(read(&test_repo, voyage).unwrap().is_empty());
+
+        let mut entry = ReflogEntry::new(&test_repo, None, &first, "reg: First\n\nbody").unwrap();
+        entry.timestamp = 1685588400;
+        append(&test_repo, voyage, &entry).unwrap();
+        let mut entry =
+            ReflogEntry::new(&test_repo, Some(first.clone()), &second, "bookmark -r").unwrap();
+        entry.timestamp = 1685674800;
+        append(&test_repo, voyage, &entry).unwrap();
+
+        let entries = read(&test_repo, voyage).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0], entry);
+        assert_eq!(entries[1].old, None);
+        assert_eq!(entries[1].reason, "reg: First");
+
+        // N updates before
+        assert_eq!(lookup(&test_repo, voyage, "0").unwrap(), second);
+        assert_eq!(lookup(&test_repo, voyage, "1").unwrap(), first);
+        assert!(lookup(&test_repo, voyage, "2").is_err());
+
+        // At the date
+        assert_eq!(
+            lookup(&test_repo, voyage, "2023-06-01T12:00:00+09:00").unwrap(),
+            first
+        );
+        assert_eq!(lookup(&test_repo, voyage, "1.day.ago").unwrap(), second);
+        assert!(lookup(&test_repo, voyage, "2023-05-01").is_err());
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+}
@@ -0,0 +1,637 @@
+//! References under `.nss/bookmarks/`.
+//!
+//! - `bookmarks/local/<bookmarker>`: local bookmark (may be nested like `feature/login`)
+//! - `bookmarks/remote/<remote>/<bookmarker>`: remote bookmark
+//!
+//! Every update of HEAD and bookmarks goes through [`RefTransaction`], which
+//! applies the updates only if each reference still holds the expected value,
+//! so concurrent processes can't clobber each other.
+
+// Std
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// External
+use anyhow::{bail, Result};
+
+// Internal
+use super::head::Head;
+use super::lock::LockFile;
+use super::reflog::{self, ReflogEntry};
+use nss_core::repository::NssRepository;
+
+/// Name of HEAD in [`RefTransaction`]
+pub const HEAD: &str = "HEAD";
+
+pub const LOCAL_PREFIX: &str = "bookmarks/local/";
+const REMOTE_PREFIX: &str = "bookmarks/remote/";
+
+/// Reference file and the commit hash written in it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    /// Path from `.nss` (e.g. `bookmarks/local/voyage`)
+    pub path: String,
+    pub hash: String,
+}
+
+impl Reference {
+    pub fn is_remote(&self) -> bool {
+        self.path.starts_with(REMOTE_PREFIX)
+    }
+
+    /// Name to display (`voyage`, `origin/voyage`).
+    pub fn short_name(&self) -> &str {
+        self.path
+            .strip_prefix(LOCAL_PREFIX)
+            .or_else(|| self.path.strip_prefix(REMOTE_PREFIX))
+            .or_else(|| self.path.strip_prefix("bookmarks/"))
+            .unwrap_or(&self.path)
+    }
+}
+
+/// Label attached to the commit in the history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decoration {
+    /// Detached HEAD
+    Head,
+    /// HEAD and the bookmark it points to
+    HeadTo(String),
+    Local(String),
+    Remote(String),
+}
+
+pub fn bookmarks_dir(repository: &NssRepository) -> PathBuf {
+    repository.path().join(".nss").join("bookmarks")
+}
+
+/// Path from `.nss` of the local bookmark.
+pub fn local_path(bookmarker: &str) -> String {
+    format!("{}{}", LOCAL_PREFIX, bookmarker)
+}
+
+/// Check the bookmarker can be a file name under `bookmarks/local`
+/// (`/` separates nested directories like `feature/login`).
+pub fn check_bookmarker(bookmarker: &str) -> Result<()> {
+    let invalid = bookmarker.is_empty()
+        || bookmarker == HEAD
+        || bookmarker.contains("@{")
+        || bookmarker
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || "~^:?*[\\".contains(c))
+        || bookmarker.split('/').any(|component| {
+            component.is_empty() || component.starts_with('.') || component.ends_with(".lock")
+        });
+
+    match invalid {
+        true => bail!("{} is not a valid bookmarker", bookmarker),
+        false => Ok(()),
+    }
+}
+
+/// Reference of the name given by the user (`HEAD`, `voyage` or `bookmarks/local/voyage`).
+pub fn reference_of(name: &str) -> String {
+    match name {
+        HEAD => HEAD.to_string(),
+        n if n.starts_with("bookmarks/") => n.to_string(),
+        n => local_path(n),
+    }
+}
+
+/// All references pointing to a commit, sorted by path.
+pub fn all_references(repository: &NssRepository) -> Result<Vec<Reference>> {
+    let nss_dir = repository.path().join(".nss");
+    let mut references = vec![];
+    let mut stack = vec![bookmarks_dir(repository)];
+
+    while let Some(dir) = stack.pop() {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                stack.push(path);
+                continue;
+            }
+            // Reference being updated
+            if path.extension().map_or(false, |e| e == "lock") {
+                continue;
+            }
+
+            let hash = fs::read_to_string(&path)?.trim().to_string();
+            if hash.is_empty() {
+                continue;
+            }
+            let relative = path.strip_prefix(&nss_dir)?;
+            references.push(Reference {
+                path: relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/"),
+                hash,
+            });
+        }
+    }
+    references.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(references)
+}
+
+/// Reverse map from commit hash to the references pointing to it (HEAD first).
+pub fn decorations(repository: &NssRepository) -> Result<HashMap<String, Vec<Decoration>>> {
+    let mut decorations: HashMap<String, Vec<Decoration>> = HashMap::new();
+    let head = Head::read(repository)?;
+
+    for reference in all_references(repository)? {
+        let name = reference.short_name().to_string();
+        let labels = decorations.entry(reference.hash.clone()).or_default();
+        if matches!(&head, Head::Symbolic(path) if *path == reference.path) {
+            labels.insert(0, Decoration::HeadTo(name));
+        } else if reference.is_remote() {
+            labels.push(Decoration::Remote(name));
+        } else {
+            labels.push(Decoration::Local(name));
+        }
+    }
+
+    // Detached HEAD points to the commit directly
+    if let Head::Detached(hash) = head {
+        decorations
+            .entry(hash)
+            .or_default()
+            .insert(0, Decoration::Head);
+    }
+
+    Ok(decorations)
+}
+
+/// Value the reference must hold for the update to be applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expected {
+    /// Not checked
+    Any,
+    /// No commit yet (empty or missing)
+    Empty,
+    /// Commit hash (or reference written in HEAD)
+    Value(String),
+}
+
+impl From<Option<String>> for Expected {
+    fn from(value: Option<String>) -> Self {
+        match value {
+            Some(value) => Self::Value(value),
+            None => Self::Empty,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RefUpdate {
+    reference: String,
+    expected: Expected,
+    /// `None` to delete the reference
+    new: Option<String>,
+}
+
+/// Locked reference in a transaction.
+struct Locked<'u> {
+    lock: LockFile,
+    update: &'u RefUpdate,
+    /// Content of the file, `None` if it was missing
+    original: Option<String>,
+    /// Value of the reference, `None` if it has no commit yet
+    current: Option<String>,
+}
+
+/// Update (or delete) one or more references at once.
+///
+/// **Note:** All references are locked and checked, and their new contents
+/// written, before any of them is replaced. If one of them does not hold the
+/// expected value, nothing is updated, and if replacing one fails, the ones
+/// already replaced are put back.
+#[derive(Debug)]
+pub struct RefTransaction<'a> {
+    repository: &'a NssRepository,
+    /// Recorded in the reflog (e.g. `reg: Fix story`)
+    reason: String,
+    updates: Vec<RefUpdate>,
+}
+
+impl<'a> RefTransaction<'a> {
+    pub fn new(repository: &'a NssRepository, reason: &str) -> Self {
+        Self {
+            repository,
+            reason: reason.to_string(),
+            updates: vec![],
+        }
+    }
+
+    /// Point the reference ([`HEAD`] or path from `.nss`) to the new value.
+    pub fn update(mut self, reference: &str, expected: Expected, new: &str) -> Self {
+        self.updates.push(RefUpdate {
+            reference: reference.to_string(),
+            expected,
+            new: Some(new.to_string()),
+        });
+
+        self
+    }
+
+    /// Delete the bookmark (path from `.nss`).
+    ///
+    /// **Note:** The reflog is left to the caller ([`reflog::remove`] or [`reflog::rename`]).
+    pub fn delete(mut self, reference: &str, expected: Expected) -> Self {
+        self.updates.push(RefUpdate {
+            reference: reference.to_string(),
+            expected,
+            new: None,
+        });
+
+        self
+    }
+
+    pub fn commit(mut self) -> Result<()> {
+        // Bookmarks are created before others are deleted (e.g. renamed)
+        self.updates.sort_by_key(|u| u.new.is_none());
+
+        let mut locked = match self.lock_all() {
+            Ok(locked) => locked,
+            Err(e) => {
+                self.prune_dirs();
+                return Err(e);
+            }
+        };
+
+        // Entries are made while the references are still locked
+        let head = Head::read(self.repository).ok();
+        let mut logs = vec![];
+        for item in &locked {
+            // Deleted, or still no commit
+            let Some(new) = item.update.new.as_ref().and_then(|n| self.new_commit_of(n)) else {
+                continue;
+            };
+            let entry = ReflogEntry::new(
+                self.repository,
+                item.current.as_ref().and_then(|c| self.commit_of(c)),
+                &new,
+                &self.reason,
+            )?;
+
+            // HEAD moves together with the bookmark it points to
+            let with_head = matches!(&head, Some(Head::Symbolic(path)) if *path == item.update.reference)
+                && !self.updates.iter().any(|u| u.reference == HEAD);
+            logs.push((item.update, entry, with_head));
+        }
+
+        // Every new content is written before any reference is replaced
+        for item in locked.iter_mut() {
+            match (item.update.reference.as_str(), &item.update.new) {
+                (HEAD, Some(new)) => item
+                    .lock
+                    .write(Head::from_value(new).to_content().as_bytes())?,
+                (_, Some(new)) => item.lock.write(new.as_bytes())?,
+                (_, None) => (),
+            }
+        }
+
+        let mut replaced = vec![];
+        for item in locked {
+            let path = item.lock.path().to_path_buf();
+            let result = match item.update.new {
+                Some(_) => item.lock.commit(),
+                None => fs::remove_file(&path).map_err(Into::into),
+            };
+            if let Err(e) = result {
+                if let Err(restore) = restore(&replaced) {
+                    bail!("{}\nCan't restore the references ({})", e, restore)
+                }
+                self.prune_dirs();
+                return Err(e);
+            }
+            replaced.push((path, item.original));
+        }
+        self.prune_dirs();
+
+        // Logged only after every reference is really replaced
+        for (update, entry, with_head) in logs {
+            reflog::append(self.repository, &update.reference, &entry)?;
+            if with_head {
+                reflog::append(self.repository, HEAD, &entry)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lock every reference and check that it holds the expected value.
+    fn lock_all(&self) -> Result<Vec<Locked>> {
+        let mut locked: Vec<Locked> = vec![];
+        for update in &self.updates {
+            if locked
+                .iter()
+                .any(|l| l.update.reference == update.reference)
+            {
+                bail!("{} is updated twice in a transaction", update.reference)
+            }
+
+            let path = self.file_path(&update.reference);
+            // Nested bookmark like `feature/login`
+            if update.new.is_some() {
+                fs::create_dir_all(path.parent().unwrap())?;
+            }
+            let lock = LockFile::acquire(path)?;
+            let original = match fs::read_to_string(lock.path()) {
+                Ok(content) => Some(content),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e.into()),
+            };
+            let current = value_of(&update.reference, original.as_deref())?;
+            let matched = match &update.expected {
+                Expected::Any => true,
+                Expected::Empty => current.is_none(),
+                Expected::Value(value) => current.as_ref() == Some(value),
+            };
+            if !matched {
+                bail!(
+                    "{} was moved by another process to {}",
+                    update.reference,
+                    current.as_deref().unwrap_or("nothing")
+                )
+            }
+            locked.push(Locked {
+                lock,
+                update,
+                original,
+                current,
+            });
+        }
+
+        Ok(locked)
+    }
+
+    /// Remove the directories of nested bookmarks left empty.
+    fn prune_dirs(&self) {
+        let local = bookmarks_dir(self.repository).join("local");
+        for update in &self.updates {
+            let path = self.file_path(&update.reference);
+            if !path.exists() {
+                let _ = prune_dirs(&path, &local);
+            }
+        }
+    }
+
+    /// Commit hash of the value after this transaction.
+    fn new_commit_of(&self, value: &str) -> Option<String> {
+        match self.updates.iter().find(|u| u.reference == value) {
+            Some(update) => update.new.clone().filter(|h| !h.is_empty()),
+            None => self.commit_of(value),
+        }
+    }
+
+    /// Commit hash of the value (HEAD may point to a bookmark).
+    fn commit_of(&self, value: &str) -> Option<String> {
+        match value.starts_with("bookmarks/") {
+            true => fs::read_to_string(self.repository.path().join(".nss").join(value))
+                .ok()
+                .map(|h| h.trim().to_string())
+                .filter(|h| !h.is_empty()),
+            false => Some(value.to_string()),
+        }
+    }
+
+    fn file_path(&self, reference: &str) -> PathBuf {
+        match reference {
+            HEAD => self.repository.head_path(),
+            _ => self.repository.path().join(".nss").join(reference),
+        }
+    }
+}
+
+/// Remove the directories left empty up to the root (exclusive).
+pub fn prune_dirs(path: &Path, root: &Path) -> Result<()> {
+    for dir in path.ancestors().skip(1) {
+        if dir == root || !dir.starts_with(root) {
+            break;
+        }
+        match fs::read_dir(dir)?.next() {
+            Some(_) => break,
+            None => fs::remove_dir(dir)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Value in the reference file content, `None` if it has no commit yet.
+fn value_of(reference: &str, content: Option<&str>) -> Result<Option<String>> {
+    let value = match (reference, content) {
+        (_, None) => return Ok(None),
+        (HEAD, Some(content)) => Head::parse(content)?.value().to_string(),
+        (_, Some(content)) => content.trim().to_string(),
+    };
+
+    match value.is_empty() {
+        true => Ok(None),
+        false => Ok(Some(value)),
+    }
+}
+
+/// Put back the references replaced before a failure (`None` if it was missing).
+fn restore(replaced: &[(PathBuf, Option<String>)]) -> Result<()> {
+    for (path, original) in replaced.iter().rev() {
+        let mut lock = LockFile::acquire(path)?;
+        match original {
+            Some(content) => {
+                lock.write(content.as_bytes())?;
+                lock.commit()?;
+            }
+            None => fs::remove_file(path)?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::voyage;
+    use testdir::testdir;
+
+    #[test]
+    fn test_decorations() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        let first = "1".repeat(40);
+        let second = "2".repeat(40);
+        let bookmarks = bookmarks_dir(&test_repo);
+        fs::create_dir_all(bookmarks.join("local").join("feature")).unwrap();
+        fs::create_dir_all(bookmarks.join("remote").join("origin")).unwrap();
+        fs::write(bookmarks.join("local").join("voyage"), &second).unwrap();
+        fs::write(
+            bookmarks.join("local").join("feature").join("login"),
+            &second,
+        )
+        .unwrap();
+        fs::write(bookmarks.join("local").join("empty"), "").unwrap();
+        fs::write(
+            bookmarks.join("remote").join("origin").join("voyage"),
+            &first,
+        )
+        .unwrap();
+
+        let references = all_references(&test_repo).unwrap();
+        assert_eq!(references.len(), 3);
+        assert_eq!(references[0].path, "bookmarks/local/feature/login");
+        assert_eq!(references[0].short_name(), "feature/login");
+        assert!(references[2].is_remote());
+        assert_eq!(references[2].short_name(), "origin/voyage");
+
+        let labels = decorations(&test_repo).unwrap();
+        assert_eq!(
+            labels[&second],
+            vec![
+                Decoration::HeadTo("voyage".to_string()),
+                Decoration::Local("feature/login".to_string())
+            ]
+        );
+        assert_eq!(
+            labels[&first],
+            vec![Decoration::Remote("origin/voyage".to_string())]
+        );
+
+        // Detached HEAD
+        fs::write(test_repo.head_path(), format!("bookmarker: {}", first)).unwrap();
+        let labels = decorations(&test_repo).unwrap();
+        assert_eq!(
+            labels[&first],
+            vec![
+                Decoration::Head,
+                Decoration::Remote("origin/voyage".to_string())
+            ]
+        );
+        assert_eq!(labels[&second][0], Decoration::Local("voyage".to_string()));
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_check_bookmarker() {
+        assert!(check_bookmarker("voyage").is_ok());
+        assert!(check_bookmarker("feature/login").is_ok());
+        assert!(check_bookmarker("v0.1.7").is_ok());
+
+        assert!(check_bookmarker("").is_err());
+        assert!(check_bookmarker("HEAD").is_err());
+        assert!(check_bookmarker("feature/").is_err());
+        assert!(check_bookmarker("/feature").is_err());
+        assert!(check_bookmarker("feature//login").is_err());
+        assert!(check_bookmarker("../voyage").is_err());
+        assert!(check_bookmarker("voyage.lock").is_err());
+        assert!(check_bookmarker("voyage@{1}").is_err());
+        assert!(check_bookmarker("fix story").is_err());
+        assert!(check_bookmarker("voyage~1").is_err());
+    }
+
+    #[test]
+    fn test_ref_transaction() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        let first = "1".repeat(40);
+        let second = "2".repeat(40);
+        let voyage = local_path("voyage");
+        let read = |path: &str| fs::read_to_string(temp_dir.join(".nss").join(path)).unwrap();
+
+        // First commit on the empty bookmark
+        RefTransaction::new(&test_repo, "test")
+            .update(&voyage, Expected::Empty, &first)
+            .commit()
+            .unwrap();
+        assert_eq!(read(&voyage), first);
+
+        // Moved by someone else: nothing is updated
+        let result = RefTransaction::new(&test_repo, "test")
+            .update(&local_path("topic"), Expected::Empty, &first)
+            .update(&voyage, Expected::Value(second.clone()), &first)
+            .commit();
+        assert!(result.is_err());
+        assert!(!temp_dir.join(".nss").join(local_path("topic")).exists());
+        assert!(!temp_dir
+            .join(".nss")
+            .join(format!("{}.lock", voyage))
+            .exists());
+
+        // HEAD and bookmark together
+        RefTransaction::new(&test_repo, "test")
+            .update(&voyage, Expected::Value(first.clone()), &second)
+            .update(HEAD, Expected::Value(voyage.clone()), &second)
+            .commit()
+            .unwrap();
+        assert_eq!(read(&voyage), second);
+        assert_eq!(
+            Head::read(&test_repo).unwrap(),
+            Head::Detached(second.clone())
+        );
+
+        // Every update is logged, the first one on HEAD through the bookmark
+        let voyage_log = reflog::read(&test_repo, &voyage).unwrap();
+        assert_eq!(voyage_log.len(), 2);
+        assert_eq!(voyage_log[1].old, None);
+        assert_eq!(voyage_log[0].new, second);
+        let head_log = reflog::read(&test_repo, HEAD).unwrap();
+        assert_eq!(head_log.len(), 2);
+        assert_eq!(head_log[1].new, first);
+        assert_eq!(head_log[0].old, Some(first.clone()));
+
+        // Nested bookmark is created and deleted with its directory
+        let login = local_path("feature/login");
+        RefTransaction::new(&test_repo, "test")
+            .update(&login, Expected::Empty, &first)
+            .commit()
+            .unwrap();
+        assert_eq!(read(&login), first);
+        RefTransaction::new(&test_repo, "test")
+            .delete(&login, Expected::Value(first.clone()))
+            .commit()
+            .unwrap();
+        let local = bookmarks_dir(&test_repo).join("local");
+        assert!(!local.join("feature").exists());
+        assert!(local.join("voyage").exists());
+
+        // Failed check leaves no directory of the nested bookmark
+        let result = RefTransaction::new(&test_repo, "test")
+            .update(&login, Expected::Empty, &first)
+            .update(&voyage, Expected::Value(first.clone()), &first)
+            .commit();
+        assert!(result.is_err());
+        assert!(!local.join("feature").exists());
+        assert_eq!(read(&voyage), second);
+
+        // Replaced references are put back as they were
+        let topic = local.join("topic");
+        fs::write(local.join("voyage"), &first).unwrap();
+        fs::write(&topic, &first).unwrap();
+        restore(&[
+            (local.join("voyage"), Some(second.clone())),
+            (topic.clone(), None),
+        ])
+        .unwrap();
+        assert_eq!(read(&voyage), second);
+        assert!(!topic.exists());
+        assert!(!temp_dir
+            .join(".nss")
+            .join(format!("{}.lock", voyage))
+            .exists());
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+}
@@ -0,0 +1,285 @@
+//! Revision expression resolver.
+//!
+//! Accepts the following expressions wherever a hash is expected.
+//!
+//! - `HEAD`: commit pointed by HEAD
+//! - `<bookmarker>`: commit pointed by the bookmark
+//! - `<hash>`: full or unique abbreviated (at least 4 characters) hash
+//! - `<rev>~<n>`: n-th first parent (`<rev>~` is `<rev>~1`)
+//! - `<rev>^<n>`: n-th parent (`<rev>^` is `<rev>^1`, `<rev>^0` is itself)
+//! - `<ref>@{<n>}`: n-th previous value of HEAD or the bookmark in its reflog
+//! - `<ref>@{<date>}`: value of HEAD or the bookmark at the date (e.g. `voyage@{2.days.ago}`)
+
+// Std
+use std::fs;
+
+// External
+use anyhow::{bail, Context, Result};
+
+// Internal
+use super::head::Head;
+use super::parents::Parents;
+use super::{reflog, refs};
+use nss_core::repository::NssRepository;
+use nss_core::struct_set::{Commit, Object};
+
+/// Shortest abbreviated hash to search the object database
+const MIN_ABBREV_LEN: usize = 4;
+
+/// Commit hash pointed by HEAD, `None` before the first commit.
+pub fn head_hash(repository: &NssRepository) -> Result<Option<String>> {
+    match Head::read(repository)? {
+        Head::Symbolic(path) => {
+            let hash = fs::read_to_string(repository.path().join(".nss").join(&path))
+                .with_context(|| format!("No such bookmark: {}", path))?;
+            match hash.trim() {
+                "" => Ok(None),
+                h => Ok(Some(h.to_string())),
+            }
+        }
+        Head::Detached(hash) => Ok(Some(hash)),
+    }
+}
+
+/// Commit hash pointed by the bookmark, `None` if it has no commit yet.
+pub fn read_bookmark(repository: &NssRepository, bookmarker: &str) -> Result<Option<String>> {
+    let hash = fs::read_to_string(repository.bookmarks_path(bookmarker))
+        .with_context(|| format!("No such bookmarker: {}", bookmarker))?;
+
+    match hash.trim() {
+        "" => Ok(None),
+        h => Ok(Some(h.to_string())),
+    }
+}
+
+/// Resolve the expression into full object hash.
+pub fn resolve(repository: &NssRepository, expr: &str) -> Result<String> {
+    let split = expr.find(['~', '^']).unwrap_or(expr.len());
+    let (base, mut suffix) = expr.split_at(split);
+
+    let mut hash = resolve_base(repository, base)?;
+
+    while let Some(op) = suffix.chars().next() {
+        let digits = suffix[1..]
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+        let number = match digits {
+            0 => 1,
+            _ => suffix[1..1 + digits]
+                .parse::<usize>()
+                .with_context(|| format!("Invalid revision: {}", expr))?,
+        };
+        suffix = &suffix[1 + digits..];
+
+        match op {
+            '~' => {
+                for _ in 0..number {
+                    hash = match Parents::of(&read_commit(repository, &hash)?)?.first() {
+                        Some(parent) => parent.to_string(),
+                        None => bail!("{} has no parent commit ({})", hash, expr),
+                    };
+                }
+            }
+            '^' if number == 0 => {
+                read_commit(repository, &hash)?;
+            }
+            '^' => {
+                hash = match Parents::of(&read_commit(repository, &hash)?)?.get(number - 1) {
+                    Some(parent) => parent.to_string(),
+                    None => bail!("{} has no parent {} ({})", hash, number, expr),
+                };
+            }
+            _ => bail!("Invalid revision: {}", expr),
+        }
+    }
+
+    Ok(hash)
+}
+
+/// Resolve the expression and check that it points to a commit.
+pub fn resolve_commit(repository: &NssRepository, expr: &str) -> Result<String> {
+    let hash = resolve(repository, expr)?;
+    read_commit(repository, &hash)?;
+
+    Ok(hash)
+}
+
+fn resolve_base(repository: &NssRepository, base: &str) -> Result<String> {
+    if let Some((name, selector)) = base.strip_suffix('}').and_then(|b| b.split_once("@{")) {
+        let name = match name {
+            "" => "HEAD",
+            n => n,
+        };
+        return reflog::lookup(repository, &refs::reference_of(name), selector);
+    }
+
+    if base == "HEAD" {
+        return match head_hash(repository)? {
+            Some(hash) => Ok(hash),
+            None => bail!("HEAD does not point to any commit yet"),
+        };
+    }
+
+    if repository.bookmarks_path(base).is_file() {
+        return match read_bookmark(repository, base)? {
+            Some(hash) => Ok(hash),
+            None => bail!("{} does not point to any commit yet", base),
+        };
+    }
+
+    if !base.chars().all(|c| c.is_ascii_hexdigit()) || base.len() > 40 {
+        bail!("Unknown revision: {}", base);
+    }
+    let base = base.to_ascii_lowercase();
+
+    if base.len() == 40 {
+        return match repository.objects_path(&base).is_file() {
+            true => Ok(base),
+            false => bail!("No such object: {}", base),
+        };
+    }
+
+    if base.len() < MIN_ABBREV_LEN {
+        bail!(
+            "Abbreviated hash must be at least {} characters: {}",
+            MIN_ABBREV_LEN,
+            base
+        );
+    }
+
+    let candidates = abbrev_candidates(repository, &base)?;
+    match candidates.len() {
+        0 => bail!("Unknown revision: {}", base),
+        1 => Ok(candidates[0].clone()),
+        _ => bail!(
+            "Ambiguous hash {}, candidates are:\n  {}",
+            base,
+            candidates.join("\n  ")
+        ),
+    }
+}
+
+/// Scan `objects/xx/` fanout for objects whose hash starts with the prefix.
+fn abbrev_candidates(repository: &NssRepository, prefix: &str) -> Result<Vec<String>> {
+    let (dir, rest) = prefix.split_at(2);
+    let fanout = repository.path().join(".nss").join("objects").join(dir);
+
+    let entries = match fs::read_dir(fanout) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(vec![]),
+    };
+
+    let mut candidates = vec![];
+    for entry in entries {
+        let name = entry?.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with(rest) {
+            candidates.push(format!("{}{}", dir, name));
+        }
+    }
+    candidates.sort();
+
+    Ok(candidates)
+}
+
+fn read_commit(repository: &NssRepository, hash: &str) -> Result<Commit> {
+    match repository.read_object(hash)? {
+        Object::Commit(commit) => Ok(commit),
+        _ => bail!("Not commit hash ({})", hash),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::voyage;
+    use crate::util::fixture::write_commit;
+    use crate::util::refs::{Expected, RefTransaction};
+    use testdir::testdir;
+
+    #[test]
+    fn test_resolve() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        // No commit yet
+        assert!(resolve(&test_repo, "HEAD").is_err());
+
+        let first = write_commit(&test_repo, &[], "first");
+        let second = write_commit(&test_repo, &[&first], "second");
+        let third = write_commit(&test_repo, &[&second], "third");
+        fs::write(test_repo.bookmarks_path("voyage"), &third).unwrap();
+
+        assert_eq!(resolve(&test_repo, "HEAD").unwrap(), third);
+        assert_eq!(resolve(&test_repo, "voyage").unwrap(), third);
+        assert_eq!(resolve(&test_repo, &third).unwrap(), third);
+        assert_eq!(resolve(&test_repo, &third[0..7]).unwrap(), third);
+        assert_eq!(resolve(&test_repo, "HEAD~").unwrap(), second);
+        assert_eq!(resolve(&test_repo, "HEAD~2").unwrap(), first);
+        assert_eq!(resolve(&test_repo, "voyage^").unwrap(), second);
+        assert_eq!(resolve(&test_repo, "voyage^^").unwrap(), first);
+        assert_eq!(resolve(&test_repo, "HEAD~1^0").unwrap(), second);
+
+        // Beyond the root commit
+        assert!(resolve(&test_repo, "HEAD~3").is_err());
+        assert!(resolve(&test_repo, "HEAD^2").is_err());
+
+        // Too short or unknown
+        assert!(resolve(&test_repo, &third[0..3]).is_err());
+        assert!(resolve(&test_repo, "develop").is_err());
+
+        // Previous values in the reflog
+        for (old, new) in [(&third, &second), (&second, &first)] {
+            RefTransaction::new(&test_repo, "test")
+                .update(
+                    &refs::local_path("voyage"),
+                    Expected::Value(old.clone()),
+                    new,
+                )
+                .commit()
+                .unwrap();
+        }
+        assert_eq!(resolve(&test_repo, "voyage@{0}").unwrap(), first);
+        assert_eq!(resolve(&test_repo, "voyage@{1}").unwrap(), second);
+        assert_eq!(resolve(&test_repo, "HEAD@{1}~1").unwrap(), first);
+        assert_eq!(resolve(&test_repo, "@{1.hour.ago}").unwrap(), third);
+        assert!(resolve(&test_repo, "voyage@{2}").is_err());
+
+        // Detached HEAD
+        fs::write(test_repo.head_path(), format!("bookmarker: {}", first)).unwrap();
+        assert_eq!(resolve(&test_repo, "HEAD").unwrap(), first);
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_abbrev_candidates() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        let fanout = temp_dir.join(".nss").join("objects").join("ab");
+        fs::create_dir_all(&fanout).unwrap();
+        fs::write(fanout.join("cd000000000000000000000000000000000000"), b"").unwrap();
+        fs::write(fanout.join("cd111111111111111111111111111111111111"), b"").unwrap();
+
+        assert_eq!(abbrev_candidates(&test_repo, "abcd").unwrap().len(), 2);
+        assert_eq!(abbrev_candidates(&test_repo, "abcd1").unwrap().len(), 1);
+        assert!(abbrev_candidates(&test_repo, "ffff").unwrap().is_empty());
+
+        // Ambiguous prefix
+        let res = resolve(&test_repo, "abcd");
+        assert!(res.is_err());
+        assert!(res.unwrap_err().to_string().contains("Ambiguous"));
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+}
@@ -0,0 +1,247 @@
+//! Stat-based change detection of the working tree.
+//!
+//! A file whose stat data (ctime, mtime, size, inode, device, mode) is the
+//! same as recorded in the index is treated as unchanged without being read.
+//! The mode is compared as the blob mode (`100644` or `100755`), so the other
+//! permission bits do not make a file changed.
+//!
+//! **Note:** A file modified in the same second as the index was written
+//! may still have the same stat data ("racily clean"), so such a file is
+//! always rehashed.
+
+// Std
+use std::fs::{self, Metadata};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+// External
+use anyhow::Result;
+
+// Internal
+use nss_core::repository::NssRepository;
+use nss_core::struct_set::FileMeta;
+
+/// Stat data recorded in the index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatData {
+    pub ctime: u32,
+    pub ctime_nsec: u32,
+    pub mtime: u32,
+    pub mtime_nsec: u32,
+    pub dev: u32,
+    pub ino: u32,
+    pub mode: u32,
+    pub size: u32,
+}
+
+impl StatData {
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        Self {
+            ctime: metadata.ctime() as u32,
+            ctime_nsec: metadata.ctime_nsec() as u32,
+            mtime: metadata.mtime() as u32,
+            mtime_nsec: metadata.mtime_nsec() as u32,
+            dev: metadata.dev() as u32,
+            ino: metadata.ino() as u32,
+            mode: blob_mode(metadata.mode()),
+            size: metadata.size() as u32,
+        }
+    }
+
+    pub fn from_filemeta(filemeta: &FileMeta) -> Self {
+        Self {
+            ctime: filemeta.ctime,
+            ctime_nsec: filemeta.ctime_nsec,
+            mtime: filemeta.mtime,
+            mtime_nsec: filemeta.mtime_nsec,
+            dev: filemeta.dev,
+            ino: filemeta.ino,
+            mode: blob_mode(filemeta.mode),
+            size: filemeta.filesize,
+        }
+    }
+}
+
+/// Executable or not, as recorded in the tree.
+pub fn blob_mode(mode: u32) -> u32 {
+    match mode & 0o111 {
+        0 => 0o100644,
+        _ => 0o100755,
+    }
+}
+
+/// Index entry of the file from its stat data and the blob hash already computed.
+///
+/// **Note:** Take the stat data before reading the file, so a change while
+/// hashing leaves the entry outdated and the file is rehashed next time.
+pub fn filemeta(
+    repository: &NssRepository,
+    path: &Path,
+    metadata: &Metadata,
+    hash: Vec<u8>,
+) -> Result<FileMeta> {
+    let filename = path.strip_prefix(repository.path())?.to_path_buf();
+
+    Ok(FileMeta {
+        ctime: metadata.ctime() as u32,
+        ctime_nsec: metadata.ctime_nsec() as u32,
+        mtime: metadata.mtime() as u32,
+        mtime_nsec: metadata.mtime_nsec() as u32,
+        dev: metadata.dev() as u32,
+        ino: metadata.ino() as u32,
+        mode: blob_mode(metadata.mode()),
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+        filesize: metadata.size() as u32,
+        hash,
+        filename_size: filename.as_os_str().len() as u16,
+        filename,
+    })
+}
+
+fn index_path(repository: &NssRepository) -> PathBuf {
+    repository.path().join(".nss").join("INDEX")
+}
+
+/// Modified time of the index in seconds, `None` if it does not exist.
+pub fn index_mtime(repository: &NssRepository) -> Option<u32> {
+    fs::metadata(index_path(repository))
+        .ok()
+        .map(|m| m.mtime() as u32)
+}
+
+/// Whether the file can be treated as unchanged from the index entry.
+pub fn is_unchanged(filemeta: &FileMeta, metadata: &Metadata, index_mtime: Option<u32>) -> bool {
+    let current = StatData::from_metadata(metadata);
+    if current != StatData::from_filemeta(filemeta) {
+        return false;
+    }
+
+    // Racily clean: modified after (or in the same second as) the index
+    match index_mtime {
+        Some(index_mtime) => current.mtime < index_mtime,
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::voyage;
+    use nss_core::struct_set::Index;
+    use std::fs::Permissions;
+    use std::os::unix::fs::PermissionsExt;
+    use testdir::testdir;
+
+    #[test]
+    fn test_stat_data() {
+        // Create a temporary directory for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+
+        let file_path = temp_dir.join("first.rs");
+        fs::write(&file_path, "first").unwrap();
+        let before = StatData::from_metadata(&fs::metadata(&file_path).unwrap());
+        assert_eq!(
+            before,
+            StatData::from_metadata(&fs::metadata(&file_path).unwrap())
+        );
+        assert_eq!(before.size, 5);
+
+        fs::write(&file_path, "first line").unwrap();
+        let after = StatData::from_metadata(&fs::metadata(&file_path).unwrap());
+        assert_ne!(before, after);
+        assert_eq!(before.ino, after.ino);
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_blob_mode() {
+        assert_eq!(blob_mode(0o100644), 0o100644);
+        assert_eq!(blob_mode(0o100664), 0o100644);
+        assert_eq!(blob_mode(0o100600), 0o100644);
+        assert_eq!(blob_mode(0o100755), 0o100755);
+        assert_eq!(blob_mode(0o100744), 0o100755);
+    }
+
+    #[test]
+    fn test_is_unchanged() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        let file_path = temp_dir.join("first.rs");
+        fs::write(&file_path, "first").unwrap();
+        let mut index = Index::empty();
+        index.add(&test_repo, &file_path, None).unwrap();
+        let mut filemeta = index.filemetas[0].clone();
+        let metadata = fs::metadata(&file_path).unwrap();
+        let mtime = metadata.mtime() as u32;
+
+        // Unchanged file
+        assert!(is_unchanged(&filemeta, &metadata, Some(mtime + 1)));
+        assert!(!is_unchanged(&filemeta, &metadata, None));
+
+        // Only the blob mode is compared
+        filemeta.mode = 0o100644;
+        fs::set_permissions(&file_path, Permissions::from_mode(0o664)).unwrap();
+        let metadata = fs::metadata(&file_path).unwrap();
+        filemeta.ctime = metadata.ctime() as u32;
+        filemeta.ctime_nsec = metadata.ctime_nsec() as u32;
+        assert!(is_unchanged(&filemeta, &metadata, Some(mtime + 1)));
+        fs::set_permissions(&file_path, Permissions::from_mode(0o755)).unwrap();
+        let metadata = fs::metadata(&file_path).unwrap();
+        filemeta.ctime = metadata.ctime() as u32;
+        filemeta.ctime_nsec = metadata.ctime_nsec() as u32;
+        assert!(!is_unchanged(&filemeta, &metadata, Some(mtime + 1)));
+        filemeta.mode = 0o100755;
+        assert!(is_unchanged(&filemeta, &metadata, Some(mtime + 1)));
+
+        // Racily clean: modified in the same second as the index
+        assert!(!is_unchanged(&filemeta, &metadata, Some(mtime)));
+
+        // Touched file (modified after the entry was recorded)
+        filemeta.mtime -= 5;
+        assert!(!is_unchanged(&filemeta, &metadata, Some(mtime + 10)));
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_filemeta() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        fs::create_dir(temp_dir.join("src")).unwrap();
+        let file_path = temp_dir.join("src").join("first.rs");
+        fs::write(&file_path, "first").unwrap();
+        let metadata = fs::metadata(&file_path).unwrap();
+        let hash = crate::util::object::hash_blob(&file_path).unwrap();
+
+        let filemeta = filemeta(&test_repo, &file_path, &metadata, hash.clone()).unwrap();
+        assert_eq!(filemeta.filename, PathBuf::from("src/first.rs"));
+        assert_eq!(filemeta.hash, hash);
+        assert_eq!(filemeta.filesize, 5);
+        assert_eq!(
+            StatData::from_filemeta(&filemeta),
+            StatData::from_metadata(&metadata)
+        );
+
+        // Same entry as the core reading the whole file
+        let mut index = Index::empty();
+        index.add(&test_repo, &file_path, None).unwrap();
+        assert_eq!(index.filemetas[0].hash, hash);
+        assert_eq!(index.filemetas[0].filename, filemeta.filename);
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+}
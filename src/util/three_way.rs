@@ -0,0 +1,180 @@
+//! Line-based three-way merge (diff3).
+//!
+//! Both sides are compared with the common ancestor and split into
+//! stable chunks (same in all three) and unstable chunks. An unstable
+//! chunk changed on only one side takes that side, otherwise it becomes
+//! a conflict surrounded by markers.
+
+// Internal
+use super::line_diff::{self, Algorithm, DiffOp};
+
+/// Result of merging one text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeResult {
+    pub text: String,
+    pub conflicts: usize,
+}
+
+/// Merge `ours` and `theirs` changes made on `base`.
+/// Labels are written next to the conflict markers.
+pub fn merge3(
+    base: &str,
+    ours: &str,
+    theirs: &str,
+    ours_label: &str,
+    theirs_label: &str,
+) -> MergeResult {
+    let base = line_diff::split_lines(base);
+    let ours = line_diff::split_lines(ours);
+    let theirs = line_diff::split_lines(theirs);
+
+    let to_ours = matching(&base, &ours);
+    let to_theirs = matching(&base, &theirs);
+
+    let mut text = String::new();
+    let mut conflicts = 0;
+    let (mut i, mut a, mut b) = (0, 0, 0);
+
+    loop {
+        // Stable lines (equal in all three)
+        let mut stable = 0;
+        while i + stable < base.len()
+            && to_ours[i + stable] == Some(a + stable)
+            && to_theirs[i + stable] == Some(b + stable)
+        {
+            stable += 1;
+        }
+        if stable > 0 {
+            base[i..i + stable].iter().for_each(|l| text.push_str(l));
+            i += stable;
+            a += stable;
+            b += stable;
+            continue;
+        }
+
+        if i == base.len() && a == ours.len() && b == theirs.len() {
+            break;
+        }
+
+        // Unstable chunk until the next base line kept on both sides
+        let next = (i..base.len()).find(|&k| to_ours[k].is_some() && to_theirs[k].is_some());
+        let (k, ea, eb) = match next {
+            Some(k) => (k, to_ours[k].unwrap(), to_theirs[k].unwrap()),
+            None => (base.len(), ours.len(), theirs.len()),
+        };
+
+        let base_chunk = &base[i..k];
+        let ours_chunk = &ours[a..ea];
+        let theirs_chunk = &theirs[b..eb];
+
+        if ours_chunk == base_chunk || ours_chunk == theirs_chunk {
+            theirs_chunk.iter().for_each(|l| text.push_str(l));
+        } else if theirs_chunk == base_chunk {
+            ours_chunk.iter().for_each(|l| text.push_str(l));
+        } else {
+            conflicts += 1;
+            text.push_str(&format!("<<<<<<< {}\n", ours_label));
+            push_chunk(&mut text, ours_chunk);
+            text.push_str("=======\n");
+            push_chunk(&mut text, theirs_chunk);
+            text.push_str(&format!(">>>>>>> {}\n", theirs_label));
+        }
+
+        i = k;
+        a = ea;
+        b = eb;
+    }
+
+    MergeResult { text, conflicts }
+}
+
+/// Push lines making sure the chunk ends with line feed before the next marker.
+fn push_chunk(text: &mut String, chunk: &[&str]) {
+    chunk.iter().for_each(|l| text.push_str(l));
+    if !(chunk.is_empty() || text.ends_with('\n')) {
+        text.push('\n');
+    }
+}
+
+/// Line in `other` matched to each line in `base`.
+/// Content as text to merge, `None` if it is binary or not UTF-8
+/// (it would be re-encoded, so it is not merged line by line).
+pub fn as_text(content: &[u8]) -> Option<&str> {
+    match line_diff::is_binary(content) {
+        true => None,
+        false => std::str::from_utf8(content).ok(),
+    }
+}
+
+fn matching(base: &[&str], other: &[&str]) -> Vec<Option<usize>> {
+    let mut map = vec![None; base.len()];
+    for op in line_diff::diff(base, other, Algorithm::Myers) {
+        if let DiffOp::Equal(o, n) = op {
+            map[o] = Some(n);
+        }
+    }
+
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge3() {
+        let base = "a\nb\nc\nd\ne\n";
+
+        // Changed on different lines
+        let ours = "A\nb\nc\nd\ne\n";
+        let theirs = "a\nb\nc\nd\nE\n";
+        let result = merge3(base, ours, theirs, "HEAD", "develop");
+        assert_eq!(result.text, "A\nb\nc\nd\nE\n");
+        assert_eq!(result.conflicts, 0);
+
+        // Changed on only one side
+        let result = merge3(base, base, theirs, "HEAD", "develop");
+        assert_eq!(result.text, theirs);
+
+        // Same change on both sides
+        let result = merge3(base, ours, ours, "HEAD", "develop");
+        assert_eq!(result.text, ours);
+
+        // Insertion and deletion
+        let ours = "a\nb\nx\nc\nd\ne\n";
+        let theirs = "a\nb\nc\ne\n";
+        let result = merge3(base, ours, theirs, "HEAD", "develop");
+        assert_eq!(result.text, "a\nb\nx\nc\ne\n");
+        assert_eq!(result.conflicts, 0);
+
+        // Conflict
+        let ours = "a\nb\nours\nd\ne\n";
+        let theirs = "a\nb\ntheirs\nd\ne\n";
+        let result = merge3(base, ours, theirs, "HEAD", "develop");
+        assert_eq!(
+            result.text,
+            "a\nb\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> develop\nd\ne\n"
+        );
+        assert_eq!(result.conflicts, 1);
+
+        // Added on both sides without base
+        let result = merge3("", "x", "y\n", "HEAD", "develop");
+        assert_eq!(
+            result.text,
+            "<<<<<<< HEAD\nx\n=======\ny\n>>>>>>> develop\n"
+        );
+        assert_eq!(result.conflicts, 1);
+    }
+
+    #[test]
+    fn test_as_text() {
+        assert_eq!(as_text(b"fn main() {}\n"), Some("fn main() {}\n"));
+        assert_eq!(as_text("日本語\n".as_bytes()), Some("日本語\n"));
+        assert_eq!(as_text(b""), Some(""));
+
+        // Binary, Latin-1 and Shift_JIS
+        assert_eq!(as_text(b"\x00\x01"), None);
+        assert_eq!(as_text(b"caf\xe9\n"), None);
+        assert_eq!(as_text(b"\x93\xfa\x96\x7b\n"), None);
+    }
+}
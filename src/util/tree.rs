@@ -0,0 +1,438 @@
+//! Conversion between commit, tree and index.
+
+// Std
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+
+// External
+use anyhow::{bail, Result};
+
+// Internal
+use super::cache_tree::CachedTree;
+use nss_core::repository::NssRepository;
+use nss_core::struct_set::{Entry, Hashable, Index, Object, Tree};
+
+/// Mode of the tree entry pointing to a subtree
+pub const TREE_MODE: u32 = 0o040000;
+
+/// Read the root tree pointed by the commit.
+pub fn to_base_tree(repository: &NssRepository, target: &str) -> Result<Tree> {
+    let commit = match repository.read_object(target)? {
+        Object::Commit(c) => c,
+        _ => bail!("{} is not commit hash", target),
+    };
+
+    // target commit hash needs to have tree hash
+    match repository.read_object(&commit.tree_hash)? {
+        Object::Tree(t) => Ok(t),
+        _ => bail!("{} is not tree hash", &commit.tree_hash),
+    }
+}
+
+/// Build the index which the commit snapshot would have.
+pub fn to_index(repository: &NssRepository, target: &str) -> Result<Index> {
+    let tree = to_base_tree(repository, target)?;
+
+    Index::try_from_tree(repository, tree)
+}
+
+/// Whether the file or directory at the path differs between two trees
+/// (`None` when there is no tree, e.g. the parent of the root commit).
+///
+/// **Note:** Only the subtrees along the path are read, and it stops as soon
+/// as both sides have the same subtree.
+pub fn changed_at(
+    repository: &NssRepository,
+    old_tree: Option<&str>,
+    new_tree: Option<&str>,
+    path: &Path,
+) -> Result<bool> {
+    let root = |hash: Option<&str>| -> Result<Option<(u32, Vec<u8>)>> {
+        Ok(match hash {
+            Some(hash) => Some((TREE_MODE, hex::decode(hash)?)),
+            None => None,
+        })
+    };
+    let mut old = root(old_tree)?;
+    let mut new = root(new_tree)?;
+
+    for component in path.components() {
+        let Component::Normal(name) = component else {
+            continue;
+        };
+        if old == new {
+            return Ok(false);
+        }
+        old = child(repository, old, name)?;
+        new = child(repository, new, name)?;
+    }
+
+    Ok(old != new)
+}
+
+/// Mode and hash of the entry named in the subtree, `None` if it is missing.
+fn child(
+    repository: &NssRepository,
+    parent: Option<(u32, Vec<u8>)>,
+    name: &OsStr,
+) -> Result<Option<(u32, Vec<u8>)>> {
+    let Some((TREE_MODE, hash)) = parent else {
+        return Ok(None);
+    };
+    let tree = match repository.read_object(hex::encode(&hash))? {
+        Object::Tree(t) => t,
+        _ => bail!("{} is not tree hash", hex::encode(&hash)),
+    };
+
+    Ok(tree
+        .entries
+        .into_iter()
+        .find(|e| e.name.as_os_str() == name)
+        .map(|e| (e.mode, e.hash)))
+}
+
+/// Tree entry made from the mode and hash recorded in the index.
+///
+/// **Note:** The working tree is never read, so the snapped content is committed.
+pub fn entry<P: AsRef<Path>>(mode: u32, name: P, hash: Vec<u8>) -> Entry {
+    Entry {
+        mode,
+        name: name.as_ref().to_path_buf(),
+        hash,
+    }
+}
+
+/// File recorded in the tree (path from the repository root).
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TreeFile {
+    path: PathBuf,
+    mode: u32,
+    hash: Vec<u8>,
+}
+
+/// Tree of the previous commit, to reuse the subtrees which have no change.
+#[derive(Debug, Default)]
+struct BaseTree {
+    /// Sorted by path
+    files: Vec<TreeFile>,
+    /// Tree hash of each directory
+    trees: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl BaseTree {
+    fn read(repository: &NssRepository, tree_hash: &str) -> Result<Self> {
+        let mut base = Self::default();
+        let mut stack = vec![(PathBuf::new(), hex::decode(tree_hash)?)];
+
+        while let Some((dir, hash)) = stack.pop() {
+            let tree = match repository.read_object(hex::encode(&hash))? {
+                Object::Tree(t) => t,
+                _ => bail!("{} is not tree hash", hex::encode(&hash)),
+            };
+            for entry in tree.entries {
+                let path = dir.join(&entry.name);
+                match entry.mode {
+                    TREE_MODE => stack.push((path, entry.hash)),
+                    mode => base.files.push(TreeFile {
+                        path,
+                        mode,
+                        hash: entry.hash,
+                    }),
+                }
+            }
+            base.trees.insert(dir, hash);
+        }
+        base.files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(base)
+    }
+
+    /// Files under the directory (contiguous in path order).
+    fn files_under(&self, dir: &Path) -> &[TreeFile] {
+        files_under(&self.files, dir)
+    }
+}
+
+fn files_under<'f>(files: &'f [TreeFile], dir: &Path) -> &'f [TreeFile] {
+    let start = files.partition_point(|f| f.path.as_path() < dir);
+    let len = files[start..]
+        .iter()
+        .take_while(|f| f.path.starts_with(dir))
+        .count();
+
+    &files[start..start + len]
+}
+
+/// Build tree objects from the index in one sorted pass, deepest directory first.
+///
+/// Unchanged subtrees are taken from the cached tree or the base commit
+/// instead of being written again.
+pub struct TreeBuilder<'a> {
+    repository: &'a NssRepository,
+    cache: Option<&'a mut CachedTree>,
+    /// Root tree hash of the base commit (read on first use)
+    base_hash: Option<String>,
+    base: Option<BaseTree>,
+}
+
+impl<'a> TreeBuilder<'a> {
+    pub fn new(repository: &'a NssRepository) -> Self {
+        Self {
+            repository,
+            cache: None,
+            base_hash: None,
+            base: None,
+        }
+    }
+
+    /// Reuse the subtrees of the commit whose files are not changed.
+    pub fn base(mut self, commit: Option<&str>) -> Result<Self> {
+        if let Some(commit) = commit {
+            let commit = match self.repository.read_object(commit)? {
+                Object::Commit(c) => c,
+                _ => bail!("{} is not commit hash", commit),
+            };
+            self.base_hash = Some(commit.tree_hash);
+        }
+
+        Ok(self)
+    }
+
+    /// Reuse and record tree hashes in the cached tree.
+    pub fn cache(mut self, cache: &'a mut CachedTree) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Write every tree of the index and return the root tree hash.
+    pub fn write(&mut self, index: &Index) -> Result<String> {
+        let files = index
+            .filemetas
+            .iter()
+            .map(|f| (f.filename.clone(), f.mode, f.hash.clone()))
+            .collect();
+
+        self.write_files(files)
+    }
+
+    /// Write every tree of the files (path, mode and blob hash) and return the root tree hash.
+    pub fn write_files(&mut self, files: Vec<(PathBuf, u32, Vec<u8>)>) -> Result<String> {
+        let mut files = files
+            .into_iter()
+            .map(|(path, mode, hash)| TreeFile { path, mode, hash })
+            .collect::<Vec<TreeFile>>();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(hex::encode(self.build(Path::new(""), &files)?))
+    }
+
+    /// Tree hash already known for the directory.
+    fn reuse(&mut self, dir: &Path, files: &[TreeFile]) -> Result<Option<Vec<u8>>> {
+        if let Some(cache) = &self.cache {
+            if let Some((count, hash)) = cache.get(dir) {
+                if count == files.len() {
+                    return Ok(Some(hex::decode(hash)?));
+                }
+            }
+        }
+
+        if let Some(tree_hash) = self.base_hash.take() {
+            self.base = Some(BaseTree::read(self.repository, &tree_hash)?);
+        }
+        if let Some(base) = &self.base {
+            if let Some(hash) = base.trees.get(dir) {
+                if base.files_under(dir) == files {
+                    return Ok(Some(hash.clone()));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Build the tree of the directory from the files under it.
+    fn build(&mut self, dir: &Path, files: &[TreeFile]) -> Result<Vec<u8>> {
+        let hash = match self.reuse(dir, files)? {
+            Some(hash) => hash,
+            None => self.build_entries(dir, files)?,
+        };
+
+        if let Some(cache) = self.cache.as_mut() {
+            cache.insert(dir, files.len(), &hex::encode(&hash));
+        }
+
+        Ok(hash)
+    }
+
+    fn build_entries(&mut self, dir: &Path, files: &[TreeFile]) -> Result<Vec<u8>> {
+        let mut entries = vec![];
+        let mut rest = files;
+        while let Some(file) = rest.first() {
+            let relative = file.path.strip_prefix(dir)?;
+            let mut components = relative.components();
+            let name = components.next().unwrap().as_os_str();
+
+            if components.next().is_none() {
+                entries.push(entry(file.mode, name, file.hash.clone()));
+                rest = &rest[1..];
+            } else {
+                let sub_dir = dir.join(name);
+                let sub_files = files_under(rest, &sub_dir);
+                let hash = self.build(&sub_dir, sub_files)?;
+                entries.push(entry(TREE_MODE, name, hash));
+                rest = &rest[sub_files.len()..];
+            }
+        }
+
+        let tree = Tree::from_entries(entries);
+        let hash = tree.to_hash();
+        if !self.repository.objects_path(hex::encode(&hash)).exists() {
+            self.repository.write_object(tree)?;
+        }
+
+        Ok(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::voyage;
+    use nss_core::struct_set::Blob;
+    use std::fs;
+    use testdir::testdir;
+
+    fn snap(repository: &NssRepository, index: &mut Index, path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+        repository.write_object(Blob::new(path).unwrap()).unwrap();
+        index.add(repository, path, None).unwrap();
+    }
+
+    #[test]
+    fn test_tree_builder() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        let mut index = Index::empty();
+        snap(&test_repo, &mut index, &temp_dir.join("first.rs"), "first");
+        snap(
+            &test_repo,
+            &mut index,
+            &temp_dir.join("src").join("a.rs"),
+            "a",
+        );
+        snap(
+            &test_repo,
+            &mut index,
+            &temp_dir.join("src").join("sub").join("b.rs"),
+            "b",
+        );
+        snap(
+            &test_repo,
+            &mut index,
+            &temp_dir.join("tests").join("c.rs"),
+            "c",
+        );
+
+        let mut cache = CachedTree::default();
+        let root = TreeBuilder::new(&test_repo)
+            .cache(&mut cache)
+            .write(&index)
+            .unwrap();
+        assert_eq!(cache.get(Path::new("")), Some((4, root.as_str())));
+        assert_eq!(cache.get(Path::new("src")).unwrap().0, 2);
+
+        // Working tree is never read
+        fs::remove_dir_all(temp_dir.join("src")).unwrap();
+        assert_eq!(TreeBuilder::new(&test_repo).write(&index).unwrap(), root);
+
+        // Valid cache is trusted, invalidated one is recomputed
+        cache.insert(Path::new("tests"), 1, &"0".repeat(40));
+        cache.invalidate(Path::new("src/sub/b.rs"));
+        let rebuilt = TreeBuilder::new(&test_repo)
+            .cache(&mut cache)
+            .write(&index)
+            .unwrap();
+        assert_ne!(rebuilt, root);
+        cache.invalidate(Path::new("tests/c.rs"));
+        let rebuilt = TreeBuilder::new(&test_repo)
+            .cache(&mut cache)
+            .write(&index)
+            .unwrap();
+        assert_eq!(rebuilt, root);
+
+        // Same snapshot as the index
+        let tree = match test_repo.read_object(&root).unwrap() {
+            Object::Tree(t) => t,
+            _ => panic!("not tree"),
+        };
+        let rebuilt = Index::try_from_tree(&test_repo, tree).unwrap();
+        let paths = |index: &Index| {
+            let mut paths = index
+                .filemetas
+                .iter()
+                .map(|f| (f.filename.clone(), f.hash.clone()))
+                .collect::<Vec<_>>();
+            paths.sort();
+            paths
+        };
+        assert_eq!(paths(&rebuilt), paths(&index));
+
+        // Subtrees of the base tree
+        let base = BaseTree::read(&test_repo, &root).unwrap();
+        assert_eq!(base.files.len(), 4);
+        assert_eq!(base.trees.len(), 4);
+        assert_eq!(base.files_under(Path::new("src")).len(), 2);
+        assert_eq!(base.files_under(Path::new("src/sub")).len(), 1);
+        assert_eq!(base.files_under(Path::new("")).len(), 4);
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_changed_at() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        let write = |b_content: &str| {
+            let mut index = Index::empty();
+            snap(&test_repo, &mut index, &temp_dir.join("first.rs"), "first");
+            snap(
+                &test_repo,
+                &mut index,
+                &temp_dir.join("src").join("sub").join("b.rs"),
+                b_content,
+            );
+            TreeBuilder::new(&test_repo).write(&index).unwrap()
+        };
+        let old = write("b");
+        let new = write("b changed");
+        let changed = |old: Option<&str>, path: &str| {
+            changed_at(&test_repo, old, Some(new.as_str()), Path::new(path)).unwrap()
+        };
+
+        assert!(changed(Some(&old), "src"));
+        assert!(changed(Some(&old), "src/sub/b.rs"));
+        assert!(changed(Some(&old), ""));
+        assert!(!changed(Some(&old), "first.rs"));
+        assert!(!changed(Some(&old), "docs"));
+        assert!(!changed(Some(&old), "src/sub/c.rs"));
+        assert!(!changed(Some(&new), "src"));
+
+        // Everything is new without the old tree
+        assert!(changed(None, "first.rs"));
+        assert!(!changed(None, "docs"));
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+}
@@ -0,0 +1,409 @@
+//! Commit graph traversal.
+//!
+//! Commits are walked iteratively with a priority queue (newest first)
+//! and a visited set, so long histories and merge commits (several
+//! parents) are handled without recursion and without duplicates.
+
+// Std
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+// External
+use anyhow::{bail, Result};
+
+// Internal
+use super::parents::Parents;
+use nss_core::repository::NssRepository;
+use nss_core::struct_set::{Commit, Object};
+
+/// Order of the commits output by the walker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Order {
+    /// Newest commit date first
+    #[default]
+    Date,
+    /// Children always before their parents (date order among siblings)
+    Topo,
+}
+
+/// Commit with its hash and parent list.
+#[derive(Clone)]
+pub struct CommitNode {
+    pub hash: String,
+    pub commit: Commit,
+    pub parents: Vec<String>,
+}
+
+impl CommitNode {
+    pub fn read(repository: &NssRepository, hash: &str) -> Result<Self> {
+        let commit = match repository.read_object(hash)? {
+            Object::Commit(commit) => commit,
+            _ => bail!("Not commit hash ({})", hash),
+        };
+        let parents = Parents::of(&commit)?.into_vec();
+
+        Ok(Self {
+            hash: hash.to_string(),
+            commit,
+            parents,
+        })
+    }
+
+    pub fn timestamp(&self) -> i64 {
+        self.commit.date.timestamp()
+    }
+
+    pub fn is_merge(&self) -> bool {
+        self.parents.len() > 1
+    }
+}
+
+/// Queue item ordered by date, then by insertion (older insertion first).
+type QueueItem = (i64, Reverse<usize>, String);
+
+pub struct RevWalk<'a> {
+    repository: &'a NssRepository,
+    order: Order,
+    queue: BinaryHeap<QueueItem>,
+    seen: HashSet<String>,
+    cache: HashMap<String, CommitNode>,
+    sequence: usize,
+    /// Remaining children of each commit (only for topological order)
+    children: Option<HashMap<String, usize>>,
+    starts: Vec<String>,
+    /// Commits never output (ancestors of hidden commits)
+    hidden: HashSet<String>,
+}
+
+impl<'a> RevWalk<'a> {
+    pub fn new(repository: &'a NssRepository, order: Order) -> Self {
+        Self {
+            repository,
+            order,
+            queue: BinaryHeap::new(),
+            seen: HashSet::new(),
+            cache: HashMap::new(),
+            sequence: 0,
+            children: None,
+            starts: vec![],
+            hidden: HashSet::new(),
+        }
+    }
+
+    /// Add a commit to start walking from.
+    pub fn push(&mut self, hash: &str) -> Result<()> {
+        if self.is_hidden(hash) {
+            return Ok(());
+        }
+        if self.seen.insert(hash.to_string()) {
+            self.starts.push(hash.to_string());
+            self.enqueue(hash)?;
+        }
+
+        Ok(())
+    }
+
+    /// Exclude the commit and all of its ancestors (`<hidden>..<pushed>`).
+    ///
+    /// **Note:** Call before [`RevWalk::push`].
+    pub fn hide(&mut self, hash: &str) -> Result<()> {
+        if !self.hidden.contains(hash) {
+            self.hidden.extend(ancestors(self.repository, hash)?);
+        }
+
+        Ok(())
+    }
+
+    pub fn is_hidden(&self, hash: &str) -> bool {
+        self.hidden.contains(hash)
+    }
+
+    fn enqueue(&mut self, hash: &str) -> Result<()> {
+        let timestamp = self.load(hash)?.timestamp();
+        self.sequence += 1;
+        self.queue
+            .push((timestamp, Reverse(self.sequence), hash.to_string()));
+
+        Ok(())
+    }
+
+    fn load(&mut self, hash: &str) -> Result<&CommitNode> {
+        if !self.cache.contains_key(hash) {
+            let node = CommitNode::read(self.repository, hash)?;
+            self.cache.insert(hash.to_string(), node);
+        }
+
+        Ok(&self.cache[hash])
+    }
+
+    /// Count children of every reachable commit before walking in topological order.
+    fn prepare_topo(&mut self) -> Result<()> {
+        let mut children: HashMap<String, usize> = HashMap::new();
+        let mut visited: HashSet<String> = HashSet::new();
+        let mut stack = self.starts.clone();
+
+        while let Some(hash) = stack.pop() {
+            if !visited.insert(hash.clone()) {
+                continue;
+            }
+            for parent in self.load(&hash)?.parents.clone() {
+                if self.is_hidden(&parent) {
+                    continue;
+                }
+                *children.entry(parent.clone()).or_default() += 1;
+                stack.push(parent);
+            }
+        }
+
+        // Only commits without children can be output first
+        self.queue = std::mem::take(&mut self.queue)
+            .into_iter()
+            .filter(|(_, _, hash)| !children.contains_key(hash))
+            .collect();
+        self.children = Some(children);
+
+        Ok(())
+    }
+
+    fn next_node(&mut self) -> Result<Option<CommitNode>> {
+        if self.order == Order::Topo && self.children.is_none() {
+            self.prepare_topo()?;
+        }
+
+        let Some((_, _, hash)) = self.queue.pop() else {
+            return Ok(None);
+        };
+        let node = match self.cache.remove(&hash) {
+            Some(node) => node,
+            None => CommitNode::read(self.repository, &hash)?,
+        };
+
+        for parent in &node.parents {
+            if self.is_hidden(parent) {
+                continue;
+            }
+            match self.children.as_mut() {
+                Some(children) => {
+                    let remains = children.entry(parent.clone()).or_default();
+                    *remains = remains.saturating_sub(1);
+                    // All children are output
+                    if *remains == 0 {
+                        self.seen.insert(parent.clone());
+                        self.enqueue(parent)?;
+                    }
+                }
+                None => {
+                    if self.seen.insert(parent.clone()) {
+                        self.enqueue(parent)?;
+                    }
+                }
+            }
+        }
+
+        Ok(Some(node))
+    }
+}
+
+impl Iterator for RevWalk<'_> {
+    type Item = Result<CommitNode>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_node().transpose()
+    }
+}
+
+/// All commits reachable from the commit (including itself).
+pub fn ancestors(repository: &NssRepository, hash: &str) -> Result<HashSet<String>> {
+    let mut walk = RevWalk::new(repository, Order::Date);
+    walk.push(hash)?;
+
+    walk.map(|node| node.map(|n| n.hash)).collect()
+}
+
+/// Sides a commit is reached from in [`merge_base`]
+const OURS: u8 = 1;
+const THEIRS: u8 = 2;
+/// Ancestor of a commit reached from both sides, so not the nearest
+const STALE: u8 = 4;
+
+/// Find the nearest commit reachable from both commits.
+///
+/// **Note:** Both sides are walked together newest first, marking each
+/// commit with the sides it is reached from (paint-down, as git does). A
+/// commit reached from both is a candidate and its ancestors become stale,
+/// so the walk stops when only stale commits are left. Candidates that are
+/// ancestors of another one (reached early by clock skew) are dropped.
+pub fn merge_base(repository: &NssRepository, ours: &str, theirs: &str) -> Result<Option<String>> {
+    if ours == theirs {
+        return Ok(Some(ours.to_string()));
+    }
+
+    let mut nodes: HashMap<String, CommitNode> = HashMap::new();
+    let mut flags: HashMap<String, u8> = HashMap::new();
+    let mut queue: BinaryHeap<QueueItem> = BinaryHeap::new();
+    let mut sequence = 0;
+    let mut candidates: Vec<String> = vec![];
+
+    for (hash, side) in [(ours, OURS), (theirs, THEIRS)] {
+        let node = CommitNode::read(repository, hash)?;
+        sequence += 1;
+        queue.push((node.timestamp(), Reverse(sequence), hash.to_string()));
+        nodes.insert(hash.to_string(), node);
+        flags.insert(hash.to_string(), side);
+    }
+
+    while queue.iter().any(|(_, _, hash)| flags[hash] & STALE == 0) {
+        let Some((_, _, hash)) = queue.pop() else {
+            break;
+        };
+        let mut flag = flags[&hash];
+        if flag & (OURS | THEIRS) == OURS | THEIRS {
+            if flag & STALE == 0 && !candidates.contains(&hash) {
+                candidates.push(hash.clone());
+            }
+            flag |= STALE;
+        }
+
+        for parent in nodes[&hash].parents.clone() {
+            let current = flags.get(&parent).copied().unwrap_or(0);
+            if current & flag == flag {
+                continue;
+            }
+            if !nodes.contains_key(&parent) {
+                let node = CommitNode::read(repository, &parent)?;
+                nodes.insert(parent.clone(), node);
+            }
+            sequence += 1;
+            queue.push((
+                nodes[&parent].timestamp(),
+                Reverse(sequence),
+                parent.clone(),
+            ));
+            flags.insert(parent, current | flag);
+        }
+    }
+
+    // Reached from a later candidate
+    candidates.retain(|hash| flags[hash] & STALE == 0);
+    if candidates.len() > 1 {
+        let mut redundant = HashSet::new();
+        for hash in &candidates {
+            let reachable = ancestors(repository, hash)?;
+            redundant.extend(
+                candidates
+                    .iter()
+                    .filter(|c| *c != hash && reachable.contains(*c))
+                    .cloned(),
+            );
+        }
+        candidates.retain(|hash| !redundant.contains(hash));
+    }
+
+    Ok(candidates.into_iter().next())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::voyage;
+    use crate::util::fixture::write_commit;
+    use std::fs;
+    use testdir::testdir;
+
+    #[test]
+    fn test_rev_walk() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        //   root - a1 - a2 - merge
+        //       \           /
+        //        b1 -------
+        let root = write_commit(&test_repo, &[], "root");
+        let a1 = write_commit(&test_repo, &[&root], "a1");
+        let a2 = write_commit(&test_repo, &[&a1], "a2");
+        let b1 = write_commit(&test_repo, &[&root], "b1");
+        let merged = write_commit(&test_repo, &[&a2, &b1], "merge");
+
+        for order in [Order::Date, Order::Topo] {
+            let mut walk = RevWalk::new(&test_repo, order);
+            walk.push(&merged).unwrap();
+            let hashes = walk.map(|n| n.unwrap().hash).collect::<Vec<String>>();
+
+            // Every commit once, children before parents
+            assert_eq!(hashes.len(), 5);
+            assert_eq!(hashes[0], merged);
+            assert_eq!(hashes[4], root);
+            let position = |h: &String| hashes.iter().position(|x| x == h).unwrap();
+            assert!(position(&a2) < position(&a1));
+        }
+
+        let node = CommitNode::read(&test_repo, &merged).unwrap();
+        assert!(node.is_merge());
+        assert_eq!(node.parents, vec![a2.clone(), b1.clone()]);
+
+        // Merged history without a2 and a1 (a2..merge)
+        for order in [Order::Date, Order::Topo] {
+            let mut walk = RevWalk::new(&test_repo, order);
+            walk.hide(&a2).unwrap();
+            walk.push(&merged).unwrap();
+            let hashes = walk.map(|n| n.unwrap().hash).collect::<Vec<String>>();
+            assert_eq!(hashes, vec![merged.clone(), b1.clone()]);
+        }
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_merge_base() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        //   root - a1 - a2
+        //       \
+        //        b1 - merge(b1, a1)
+        let root = write_commit(&test_repo, &[], "root");
+        let a1 = write_commit(&test_repo, &[&root], "a1");
+        let a2 = write_commit(&test_repo, &[&a1], "a2");
+        let b1 = write_commit(&test_repo, &[&root], "b1");
+        let merged = write_commit(&test_repo, &[&b1, &a1], "merge");
+
+        assert_eq!(
+            merge_base(&test_repo, &a2, &b1).unwrap(),
+            Some(root.clone())
+        );
+        assert_eq!(merge_base(&test_repo, &a2, &a1).unwrap(), Some(a1.clone()));
+        assert_eq!(merge_base(&test_repo, &a1, &a2).unwrap(), Some(a1.clone()));
+        assert_eq!(merge_base(&test_repo, &a2, &merged).unwrap(), Some(a1));
+        assert_eq!(merge_base(&test_repo, &a2, &a2).unwrap(), Some(a2.clone()));
+
+        // Older common ancestor reached first from theirs
+        //   root - x - ours
+        //      \    \
+        //       \    t1
+        //        \     \
+        //         ----- theirs(root, t1)
+        let x = write_commit(&test_repo, &[&root], "x");
+        let ours = write_commit(&test_repo, &[&x], "ours");
+        let t1 = write_commit(&test_repo, &[&x], "t1");
+        let theirs = write_commit(&test_repo, &[&root, &t1], "theirs");
+        assert_eq!(
+            merge_base(&test_repo, &ours, &theirs).unwrap(),
+            Some(x.clone())
+        );
+        assert_eq!(merge_base(&test_repo, &theirs, &ours).unwrap(), Some(x));
+
+        // No common history
+        let other = write_commit(&test_repo, &[], "other root");
+        assert_eq!(merge_base(&test_repo, &a2, &other).unwrap(), None);
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+}
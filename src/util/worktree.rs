@@ -0,0 +1,246 @@
+//! Replacing files in the working directory as a whole.
+//!
+//! The new files are written into `.nss/WORKTREE.staging` first, and the
+//! files being replaced or deleted are moved aside into `.nss/WORKTREE.backup`.
+//! Only then the new files are moved into place, so a failure on the way
+//! (object not found, disk full, permission denied...) puts the original
+//! files back instead of leaving the working directory half updated.
+//!
+//! Directories left empty by deleting files are removed.
+
+// Std
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// External
+use anyhow::{bail, Context, Result};
+
+// Internal
+use super::refs::prune_dirs;
+use super::{object, pool};
+use nss_core::repository::NssRepository;
+
+const STAGING: &str = "WORKTREE.staging";
+const BACKUP: &str = "WORKTREE.backup";
+
+/// New content of the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Content {
+    /// Hash of the blob
+    Blob(String),
+    Data(Vec<u8>),
+}
+
+/// File to be written, or deleted when `content` is `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Update {
+    /// Path from the repository root
+    pub path: PathBuf,
+    pub content: Option<Content>,
+}
+
+/// Apply all updates and then `finish` (e.g. writing the index).
+/// If any of them fails, the working directory is restored.
+pub fn apply<F>(repository: &NssRepository, updates: &[Update], finish: F) -> Result<()>
+where
+    F: FnOnce() -> Result<()>,
+{
+    let nss_dir = repository.path().join(".nss");
+    let staging = nss_dir.join(STAGING);
+    let backup = nss_dir.join(BACKUP);
+    if backup.exists() {
+        bail!(
+            "Previous update of the working directory was interrupted.\nIts original files are kept in {}",
+            backup.display()
+        )
+    }
+
+    let _ = fs::remove_dir_all(&staging);
+    if let Err(e) = stage(repository, &staging, updates) {
+        let _ = fs::remove_dir_all(&staging);
+        return Err(e);
+    }
+
+    let mut swap = Swap {
+        root: repository.path().to_path_buf(),
+        staging: &staging,
+        backup: &backup,
+        moved: vec![],
+        placed: vec![],
+    };
+    let result = swap.run(updates).and_then(|_| finish());
+    if let Err(e) = result {
+        if let Err(rollback) = swap.rollback() {
+            bail!(
+                "{}\nCan't restore the working directory ({})\nThe original files are kept in {}",
+                e,
+                rollback,
+                backup.display()
+            )
+        }
+        let _ = fs::remove_dir_all(&staging);
+        let _ = fs::remove_dir_all(&backup);
+        return Err(e);
+    }
+
+    let _ = fs::remove_dir_all(&staging);
+    let _ = fs::remove_dir_all(&backup);
+
+    Ok(())
+}
+
+/// Write every new file into the staging directory.
+fn stage(repository: &NssRepository, staging: &Path, updates: &[Update]) -> Result<()> {
+    let results = pool::parallel_map(updates, |update| {
+        let Some(content) = &update.content else {
+            return Ok(());
+        };
+        let path = staging.join(&update.path);
+        fs::create_dir_all(path.parent().unwrap())?;
+
+        match content {
+            Content::Blob(hash) => object::restore_blob(repository, hash, &path),
+            Content::Data(data) => Ok(fs::write(&path, data)?),
+        }
+        .with_context(|| format!("Can't write {}", update.path.display()))
+    });
+
+    results.into_iter().collect()
+}
+
+/// Files moved so far, to be undone.
+struct Swap<'a> {
+    root: PathBuf,
+    staging: &'a Path,
+    backup: &'a Path,
+    /// Original files moved into the backup
+    moved: Vec<PathBuf>,
+    /// New files moved into the working directory
+    placed: Vec<PathBuf>,
+}
+
+impl Swap<'_> {
+    fn run(&mut self, updates: &[Update]) -> Result<()> {
+        for update in updates {
+            let path = self.root.join(&update.path);
+            if fs::symlink_metadata(&path).is_err() {
+                continue;
+            }
+            let saved = self.backup.join(&update.path);
+            fs::create_dir_all(saved.parent().unwrap())?;
+            fs::rename(&path, &saved)
+                .with_context(|| format!("Can't move {}", update.path.display()))?;
+            self.moved.push(update.path.clone());
+            prune_dirs(&path, &self.root)?;
+        }
+
+        for update in updates.iter().filter(|u| u.content.is_some()) {
+            let path = self.root.join(&update.path);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::rename(self.staging.join(&update.path), &path)
+                .with_context(|| format!("Can't write {}", update.path.display()))?;
+            self.placed.push(update.path.clone());
+        }
+
+        Ok(())
+    }
+
+    fn rollback(&self) -> Result<()> {
+        for path in self.placed.iter().rev() {
+            let placed = self.root.join(path);
+            fs::remove_file(&placed)?;
+            prune_dirs(&placed, &self.root)?;
+        }
+        for path in self.moved.iter().rev() {
+            let original = self.root.join(path);
+            fs::create_dir_all(original.parent().unwrap())?;
+            fs::rename(self.backup.join(path), original)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::voyage;
+    use testdir::testdir;
+
+    #[test]
+    fn test_apply() {
+        // Create a temporary repository for testing
+        let temp_dir = testdir!();
+        println!("Test Directory: {}", temp_dir.display());
+        voyage::run(&temp_dir).unwrap();
+        let test_repo = NssRepository::new(temp_dir.clone());
+
+        fs::write(temp_dir.join("first.rs"), "first").unwrap();
+        fs::write(temp_dir.join("second.rs"), "second").unwrap();
+        fs::write(temp_dir.join("blob.rs"), "blob").unwrap();
+        let hash = hex::encode(object::store_blob(&test_repo, &temp_dir.join("blob.rs")).unwrap());
+        fs::remove_file(temp_dir.join("blob.rs")).unwrap();
+
+        let updates = vec![
+            Update {
+                path: PathBuf::from("first.rs"),
+                content: Some(Content::Data(b"changed".to_vec())),
+            },
+            Update {
+                path: PathBuf::from("second.rs"),
+                content: None,
+            },
+            Update {
+                path: PathBuf::from("src/blob.rs"),
+                content: Some(Content::Blob(hash)),
+            },
+        ];
+        let nss_dir = temp_dir.join(".nss");
+
+        // Failure after the files were moved is rolled back
+        let result = apply(&test_repo, &updates, || bail!("Failed"));
+        assert!(result.is_err());
+        assert_eq!(
+            fs::read_to_string(temp_dir.join("first.rs")).unwrap(),
+            "first"
+        );
+        assert_eq!(
+            fs::read_to_string(temp_dir.join("second.rs")).unwrap(),
+            "second"
+        );
+        assert!(!temp_dir.join("src").exists());
+        assert!(!nss_dir.join(STAGING).exists());
+        assert!(!nss_dir.join(BACKUP).exists());
+
+        // Missing object fails before the working directory is touched
+        let mut broken = updates.clone();
+        broken[2].content = Some(Content::Blob("0".repeat(40)));
+        assert!(apply(&test_repo, &broken, || Ok(())).is_err());
+        assert!(temp_dir.join("second.rs").exists());
+
+        apply(&test_repo, &updates, || Ok(())).unwrap();
+        assert_eq!(
+            fs::read_to_string(temp_dir.join("first.rs")).unwrap(),
+            "changed"
+        );
+        assert!(!temp_dir.join("second.rs").exists());
+        assert_eq!(
+            fs::read_to_string(temp_dir.join("src").join("blob.rs")).unwrap(),
+            "blob"
+        );
+        assert!(!nss_dir.join(STAGING).exists());
+        assert!(!nss_dir.join(BACKUP).exists());
+
+        // Emptied directory is removed
+        let updates = vec![Update {
+            path: PathBuf::from("src/blob.rs"),
+            content: None,
+        }];
+        apply(&test_repo, &updates, || Ok(())).unwrap();
+        assert!(!temp_dir.join("src").exists());
+        assert!(temp_dir.join("first.rs").exists());
+
+        // Clean up: Remove the test dir
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+}